@@ -1,16 +1,141 @@
 use serde::{Deserialize, Serialize};
-use serde_json;
+use std::collections::HashMap;
 use std::env;
 use std::path;
 use std::path::{Path, PathBuf};
 use tokio::fs;
 
+// framing and validators are always set by the server
+const RESERVED_HEADER_NAMES: [&str; 6] = [
+    "content-length",
+    "content-encoding",
+    "content-range",
+    "transfer-encoding",
+    "etag",
+    "last-modified",
+];
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CacheControlRule {
+    pub pattern: String,
+    pub value: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct HeaderRule {
+    pub pattern: String,
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CompressionConfig {
+    pub min_size: Option<u64>,
+    pub content_types: Option<Vec<String>>,
+    pub cache_directory: Option<PathBuf>,
+    pub cache_max_size: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TlsCertificate {
+    pub hostnames: Vec<String>,
+    pub cert_filepath: PathBuf,
+    pub key_filepath: PathBuf,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TlsConfig {
+    pub cert_filepath: PathBuf,
+    pub key_filepath: PathBuf,
+    pub certificates: Option<Vec<TlsCertificate>>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    Follow,
+    FollowWithinRoot,
+    Deny,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DotfilePolicy {
+    Allow,
+    Deny,
+    Ignore,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MountConfig {
+    pub prefix: String,
+    pub directory: PathBuf,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SpaFallbackConfig {
+    pub filepath: PathBuf,
+    pub exclude_patterns: Option<Vec<String>>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogFormat {
+    Common,
+    Combined,
+    Json,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AccessLogConfig {
+    pub format: Option<AccessLogFormat>,
+    pub filepath: Option<PathBuf>,
+    pub max_size: Option<u64>,
+    pub max_files: Option<usize>,
+}
+
+// served on the main listener unless host_and_port is set
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MetricsConfig {
+    pub path: Option<String>,
+    pub host_and_port: Option<String>,
+}
+
+// unset properties are inherited from the top level config
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SiteConfig {
+    pub hostnames: Vec<String>,
+    pub directory: PathBuf,
+    pub content_encodings: Option<Vec<String>>,
+    pub filepath_404: Option<PathBuf>,
+    pub cache_control: Option<Vec<CacheControlRule>>,
+    pub headers: Option<Vec<HeaderRule>>,
+    pub mounts: Option<Vec<MountConfig>>,
+    pub spa_fallback: Option<SpaFallbackConfig>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Config {
     pub host_and_port: String,
     pub directory: PathBuf,
     pub content_encodings: Option<Vec<String>>,
     pub filepath_404: Option<PathBuf>,
+    pub cache_control: Option<Vec<CacheControlRule>>,
+    pub headers: Option<Vec<HeaderRule>>,
+    pub compression: Option<CompressionConfig>,
+    pub tls: Option<TlsConfig>,
+    pub mounts: Option<Vec<MountConfig>>,
+    pub symlinks: Option<SymlinkPolicy>,
+    pub dotfiles: Option<DotfilePolicy>,
+    pub deny_patterns: Option<Vec<String>>,
+    pub autoindex: Option<bool>,
+    pub trailing_slash_redirect: Option<u16>,
+    pub index_files: Option<Vec<String>>,
+    pub try_extensions: Option<Vec<String>>,
+    pub spa_fallback: Option<SpaFallbackConfig>,
+    pub access_log: Option<AccessLogConfig>,
+    pub metrics: Option<MetricsConfig>,
+    pub shutdown_timeout: Option<u64>,
+    pub sites: Option<Vec<SiteConfig>>,
 }
 
 impl Config {
@@ -25,6 +150,23 @@ impl Config {
             directory: curr_dir,
             content_encodings: None,
             filepath_404: None,
+            cache_control: None,
+            headers: None,
+            compression: None,
+            tls: None,
+            mounts: None,
+            symlinks: None,
+            dotfiles: None,
+            deny_patterns: None,
+            autoindex: None,
+            trailing_slash_redirect: None,
+            index_files: None,
+            try_extensions: None,
+            spa_fallback: None,
+            access_log: None,
+            metrics: None,
+            shutdown_timeout: None,
+            sites: None,
         })
     }
 
@@ -41,7 +183,7 @@ impl Config {
         };
 
         // get target directory
-        let config_path = match path::absolute(&source_path) {
+        let config_path = match path::absolute(source_path) {
             Ok(pb) => pb,
             Err(e) => return Err(e.to_string()),
         };
@@ -69,8 +211,214 @@ impl Config {
             };
         }
 
+        if let Some(compression) = &mut config.compression {
+            if let Some(cache_directory) = &compression.cache_directory {
+                compression.cache_directory = match path::absolute(parent_dir.join(cache_directory))
+                {
+                    Ok(pb) => Some(pb),
+                    Err(e) => return Err(e.to_string()),
+                };
+            }
+        }
+
+        if let Some(tls) = &mut config.tls {
+            tls.cert_filepath = match path::absolute(parent_dir.join(&tls.cert_filepath)) {
+                Ok(pb) => pb,
+                Err(e) => return Err(e.to_string()),
+            };
+
+            tls.key_filepath = match path::absolute(parent_dir.join(&tls.key_filepath)) {
+                Ok(pb) => pb,
+                Err(e) => return Err(e.to_string()),
+            };
+
+            if let Some(certificates) = &mut tls.certificates {
+                for certificate in certificates {
+                    certificate.cert_filepath =
+                        match path::absolute(parent_dir.join(&certificate.cert_filepath)) {
+                            Ok(pb) => pb,
+                            Err(e) => return Err(e.to_string()),
+                        };
+
+                    certificate.key_filepath =
+                        match path::absolute(parent_dir.join(&certificate.key_filepath)) {
+                            Ok(pb) => pb,
+                            Err(e) => return Err(e.to_string()),
+                        };
+                }
+            }
+        }
+
+        if let Some(access_log) = &mut config.access_log {
+            if let Some(filepath) = &access_log.filepath {
+                access_log.filepath = match path::absolute(parent_dir.join(filepath)) {
+                    Ok(pb) => Some(pb),
+                    Err(e) => return Err(e.to_string()),
+                };
+            }
+
+            if access_log.max_size.is_some() && access_log.filepath.is_none() {
+                return Err("access_log max_size requires a filepath".to_string());
+            }
+
+            if Some(0) == access_log.max_size {
+                return Err("access_log max_size must be greater than 0".to_string());
+            }
+        }
+
+        if let Some(sites) = &mut config.sites {
+            for site in sites {
+                site.directory = match path::absolute(parent_dir.join(&site.directory)) {
+                    Ok(pb) => pb,
+                    Err(e) => return Err(e.to_string()),
+                };
+
+                if let Some(origin_404s) = &site.filepath_404 {
+                    site.filepath_404 = match get_path_relative_to_origin(parent_dir, origin_404s) {
+                        Ok(pb) => Some(pb),
+                        Err(e) => return Err(e.to_string()),
+                    };
+                }
+
+                resolve_mounts(parent_dir, &mut site.mounts)?;
+                resolve_spa_fallback(parent_dir, &mut site.spa_fallback)?;
+                validate_cache_control(&site.cache_control)?;
+                validate_headers(&site.headers)?;
+            }
+        }
+
+        if let Some(metrics) = &config.metrics {
+            if let Some(path) = &metrics.path {
+                if !path.starts_with("/") {
+                    return Err("metrics path must start with /".to_string());
+                }
+            }
+        }
+
+        if let Some(status) = config.trailing_slash_redirect {
+            if 301 != status && 308 != status {
+                return Err("trailing_slash_redirect must be 301 or 308".to_string());
+            }
+        }
+
+        validate_filenames(&config.index_files)?;
+        validate_filenames(&config.try_extensions)?;
+
+        resolve_mounts(parent_dir, &mut config.mounts)?;
+        resolve_spa_fallback(parent_dir, &mut config.spa_fallback)?;
+        validate_cache_control(&config.cache_control)?;
+        validate_headers(&config.headers)?;
+
         Ok(config)
     }
+
+    // a site is served with the top level config and its own properties
+    pub fn get_site_config(&self, site: &SiteConfig) -> Config {
+        let mut config = self.clone();
+        config.directory = site.directory.clone();
+        config.sites = None;
+
+        if let Some(content_encodings) = &site.content_encodings {
+            config.content_encodings = Some(content_encodings.clone());
+        }
+
+        // fallback files are only served from a site's own directory or mounts,
+        // so they are never inherited
+        config.filepath_404 = site.filepath_404.clone();
+        config.spa_fallback = site.spa_fallback.clone();
+
+        if let Some(cache_control) = &site.cache_control {
+            config.cache_control = Some(cache_control.clone());
+        }
+
+        if let Some(headers) = &site.headers {
+            config.headers = Some(headers.clone());
+        }
+
+        if let Some(mounts) = &site.mounts {
+            config.mounts = Some(mounts.clone());
+        }
+
+        config
+    }
+}
+
+fn resolve_mounts(parent_dir: &Path, mounts: &mut Option<Vec<MountConfig>>) -> Result<(), String> {
+    if let Some(mounts) = mounts {
+        for mount in mounts {
+            if !mount.prefix.starts_with("/") {
+                return Err(mount.prefix.to_string() + " mount prefix must start with /");
+            }
+
+            mount.directory = match path::absolute(parent_dir.join(&mount.directory)) {
+                Ok(pb) => pb,
+                Err(e) => return Err(e.to_string()),
+            };
+        }
+    }
+
+    Ok(())
+}
+
+fn resolve_spa_fallback(
+    parent_dir: &Path,
+    spa_fallback: &mut Option<SpaFallbackConfig>,
+) -> Result<(), String> {
+    if let Some(spa_fallback) = spa_fallback {
+        spa_fallback.filepath = match path::absolute(parent_dir.join(&spa_fallback.filepath)) {
+            Ok(pb) => pb,
+            Err(e) => return Err(e.to_string()),
+        };
+    }
+
+    Ok(())
+}
+
+// index files and extensions can't leave their directory
+fn validate_filenames(filenames: &Option<Vec<String>>) -> Result<(), String> {
+    if let Some(filenames) = filenames {
+        for filename in filenames {
+            if filename.is_empty() || filename.contains(['/', '\\']) || ".." == filename {
+                return Err(filename.to_string() + " is not a valid file name");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_cache_control(rules: &Option<Vec<CacheControlRule>>) -> Result<(), String> {
+    if let Some(rules) = rules {
+        for rule in rules {
+            if !is_valid_header_value(&rule.value) {
+                return Err("cache_control value is not a valid header value".to_string());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_headers(rules: &Option<Vec<HeaderRule>>) -> Result<(), String> {
+    if let Some(rules) = rules {
+        for rule in rules {
+            for (name, value) in &rule.headers {
+                if !is_valid_header_name(name) {
+                    return Err(name.to_string() + " is not a valid header name");
+                }
+
+                if RESERVED_HEADER_NAMES.contains(&name.to_ascii_lowercase().as_str()) {
+                    return Err(name.to_string() + " header is set by file_server");
+                }
+
+                if !is_valid_header_value(value) {
+                    return Err(name.to_string() + " value is not a valid header value");
+                }
+            }
+        }
+    }
+
+    Ok(())
 }
 
 fn get_path_relative_to_origin(source_dir: &Path, filepath: &PathBuf) -> Result<PathBuf, String> {
@@ -86,3 +434,18 @@ fn get_path_relative_to_origin(source_dir: &Path, filepath: &PathBuf) -> Result<
 
     Err("filepath_404 does not reside in source_dir".to_string())
 }
+
+// https://www.rfc-editor.org/rfc/rfc9110#name-tokens
+fn is_valid_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// visible ascii, spaces, and tabs
+fn is_valid_header_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| b == b'\t' || (b' '..=b'~').contains(&b))
+}
@@ -0,0 +1,421 @@
+use http_body_util::BodyExt;
+use hyper::header::{HeaderName, CONTENT_ENCODING, REFERER, USER_AGENT};
+use hyper::http::{HeaderMap, Request};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Stdout, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use config::{AccessLogConfig, AccessLogFormat};
+use response::BoxedResponse;
+
+use crate::counted_body::CountedBody;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const DEFAULT_MAX_FILES: usize = 5;
+const CHANNEL_CAPACITY: usize = 8192;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// one line per request, written once the response body is sent or dropped
+//
+// lines are written on a dedicated thread so a slow disk never holds up a response
+#[derive(Debug)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sender: SyncSender<LogMessage>,
+}
+
+#[derive(Debug)]
+enum LogMessage {
+    Line(String),
+    Reopen,
+    Flush(SyncSender<()>),
+}
+
+// owned by the writer thread
+struct LogWriter {
+    filepath: Option<PathBuf>,
+    max_size: Option<u64>,
+    max_files: usize,
+    output: LogOutput,
+}
+
+enum LogOutput {
+    Stdout(BufWriter<Stdout>),
+    // size is tracked for rotation
+    File(BufWriter<File>, u64),
+}
+
+// request details are copied before the request is handed off
+pub struct LogEntry {
+    time: SystemTime,
+    start: Instant,
+    remote_address: Option<SocketAddr>,
+    method: String,
+    uri: String,
+    version: String,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    status: u16,
+    content_encoding: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonLogEntry<'a> {
+    time: String,
+    remote_address: Option<String>,
+    method: &'a str,
+    path: &'a str,
+    protocol: &'a str,
+    status: u16,
+    bytes_sent: u64,
+    duration_ms: f64,
+    content_encoding: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    referer: Option<&'a str>,
+}
+
+impl AccessLog {
+    pub fn try_from(access_log_config: &AccessLogConfig) -> Result<AccessLog, String> {
+        let output = match &access_log_config.filepath {
+            Some(filepath) => open_log_file(filepath)?,
+            _ => LogOutput::Stdout(BufWriter::new(io::stdout())),
+        };
+
+        let writer = LogWriter {
+            filepath: access_log_config.filepath.clone(),
+            max_size: access_log_config.max_size,
+            max_files: access_log_config.max_files.unwrap_or(DEFAULT_MAX_FILES),
+            output,
+        };
+
+        let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+        let spawned = thread::Builder::new()
+            .name("access_log".to_string())
+            .spawn(move || writer.run(receiver));
+
+        if let Err(e) = spawned {
+            return Err("access log: ".to_string() + &e.to_string());
+        }
+
+        Ok(AccessLog {
+            format: access_log_config
+                .format
+                .clone()
+                .unwrap_or(AccessLogFormat::Combined),
+            sender,
+        })
+    }
+
+    // logrotate moves the file then signals to reopen it
+    pub fn reopen(&self) -> Result<(), String> {
+        match self.sender.send(LogMessage::Reopen) {
+            Ok(_) => Ok(()),
+            _ => Err("access log unavailable".to_string()),
+        }
+    }
+
+    // blocks until buffered lines are written, called once on shutdown
+    pub fn flush(&self) {
+        let (done_sender, done_receiver) = sync_channel(1);
+        if self.sender.send(LogMessage::Flush(done_sender)).is_ok() {
+            let _ = done_receiver.recv();
+        }
+    }
+
+    fn write(&self, entry: &LogEntry, bytes_sent: u64) {
+        let line = match self.format {
+            AccessLogFormat::Common => get_common_line(entry, bytes_sent),
+            AccessLogFormat::Combined => {
+                get_common_line(entry, bytes_sent)
+                    + " \""
+                    + &escape_log_str(entry.referer.as_deref().unwrap_or("-"))
+                    + "\" \""
+                    + &escape_log_str(entry.user_agent.as_deref().unwrap_or("-"))
+                    + "\""
+            }
+            AccessLogFormat::Json => get_json_line(entry, bytes_sent),
+        };
+
+        // lines are dropped rather than blocking a response when the writer falls behind
+        let _ = self.sender.try_send(LogMessage::Line(line));
+    }
+}
+
+impl LogWriter {
+    // buffered lines are flushed at least once every FLUSH_INTERVAL
+    fn run(mut self, receiver: Receiver<LogMessage>) {
+        let mut last_flush = Instant::now();
+
+        loop {
+            match receiver.recv_timeout(FLUSH_INTERVAL) {
+                Ok(LogMessage::Line(line)) => self.write_line(&line),
+                Ok(LogMessage::Reopen) => self.reopen(),
+                Ok(LogMessage::Flush(done_sender)) => {
+                    self.flush();
+                    let _ = done_sender.send(());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.flush();
+                    return;
+                }
+            }
+
+            if FLUSH_INTERVAL <= last_flush.elapsed() {
+                self.flush();
+                last_flush = Instant::now();
+            }
+        }
+    }
+
+    // a failed write never stops the writer
+    fn write_line(&mut self, line: &str) {
+        let size = match &mut self.output {
+            LogOutput::Stdout(stdout) => {
+                let _ = writeln!(stdout, "{}", line);
+                return;
+            }
+            LogOutput::File(file, size) => {
+                if writeln!(file, "{}", line).is_ok() {
+                    *size += line.len() as u64 + 1;
+                }
+                *size
+            }
+        };
+
+        let (filepath, max_size) = match (&self.filepath, self.max_size) {
+            (Some(fp), Some(ms)) => (fp, ms),
+            _ => return,
+        };
+
+        if size < max_size {
+            return;
+        }
+
+        // the replaced writer flushes to the renamed file when dropped
+        match rotate_log_files(filepath, self.max_files) {
+            Ok(output) => self.output = output,
+            Err(e) => {
+                eprintln!("file_server: access log not rotated: {}", e);
+                // retried after another max_size bytes rather than on every line
+                if let LogOutput::File(_, size) = &mut self.output {
+                    *size = 0;
+                }
+            }
+        }
+    }
+
+    fn reopen(&mut self) {
+        let filepath = match &self.filepath {
+            Some(fp) => fp,
+            _ => return,
+        };
+
+        match open_log_file(filepath) {
+            Ok(output) => self.output = output,
+            Err(e) => eprintln!("file_server: access log not reopened: {}", e),
+        }
+    }
+
+    fn flush(&mut self) {
+        let _ = match &mut self.output {
+            LogOutput::Stdout(stdout) => stdout.flush(),
+            LogOutput::File(file, _) => file.flush(),
+        };
+    }
+}
+
+fn open_log_file(filepath: &Path) -> Result<LogOutput, String> {
+    let file = match OpenOptions::new().create(true).append(true).open(filepath) {
+        Ok(f) => f,
+        Err(e) => return Err(filepath.display().to_string() + ": " + &e.to_string()),
+    };
+
+    let size = match file.metadata() {
+        Ok(md) => md.len(),
+        Err(e) => return Err(filepath.display().to_string() + ": " + &e.to_string()),
+    };
+
+    Ok(LogOutput::File(BufWriter::new(file), size))
+}
+
+// "access.log" -> "access.log.1" -> "access.log.2", the oldest is removed
+fn rotate_log_files(filepath: &Path, max_files: usize) -> Result<LogOutput, String> {
+    if 0 == max_files {
+        if let Err(e) = std::fs::remove_file(filepath) {
+            return Err(filepath.display().to_string() + ": " + &e.to_string());
+        }
+
+        return open_log_file(filepath);
+    }
+
+    for index in (1..max_files).rev() {
+        let source = get_rotated_path(filepath, index);
+        if source.exists() {
+            let _ = std::fs::rename(&source, get_rotated_path(filepath, index + 1));
+        }
+    }
+
+    if let Err(e) = std::fs::rename(filepath, get_rotated_path(filepath, 1)) {
+        return Err(filepath.display().to_string() + ": " + &e.to_string());
+    }
+
+    open_log_file(filepath)
+}
+
+fn get_rotated_path(filepath: &Path, index: usize) -> PathBuf {
+    let mut rotated = filepath.as_os_str().to_os_string();
+    rotated.push(".");
+    rotated.push(index.to_string());
+
+    PathBuf::from(rotated)
+}
+
+impl LogEntry {
+    pub fn from(
+        req: &Request<hyper::body::Incoming>,
+        remote_address: Option<SocketAddr>,
+    ) -> LogEntry {
+        let uri = match req.uri().path_and_query() {
+            Some(pq) => pq.to_string(),
+            _ => req.uri().path().to_string(),
+        };
+
+        LogEntry {
+            time: SystemTime::now(),
+            start: Instant::now(),
+            remote_address,
+            method: req.method().to_string(),
+            uri,
+            version: format!("{:?}", req.version()),
+            user_agent: get_header_string(req.headers(), USER_AGENT),
+            referer: get_header_string(req.headers(), REFERER),
+            status: 0,
+            content_encoding: None,
+        }
+    }
+}
+
+pub fn log_response(
+    access_log: Arc<AccessLog>,
+    mut entry: LogEntry,
+    response: BoxedResponse,
+) -> BoxedResponse {
+    entry.status = response.status().as_u16();
+    entry.content_encoding = get_header_string(response.headers(), CONTENT_ENCODING);
+
+    response.map(|body| {
+        CountedBody::from(body, move |bytes_sent| access_log.write(&entry, bytes_sent)).boxed()
+    })
+}
+
+// https://httpd.apache.org/docs/current/logs.html#common
+fn get_common_line(entry: &LogEntry, bytes_sent: u64) -> String {
+    let remote_address = match entry.remote_address {
+        Some(addr) => addr.ip().to_string(),
+        _ => "-".to_string(),
+    };
+
+    let bytes = match bytes_sent {
+        0 => "-".to_string(),
+        _ => bytes_sent.to_string(),
+    };
+
+    remote_address
+        + " - - ["
+        + &get_clf_date(entry.time)
+        + "] \""
+        + &escape_log_str(&entry.method)
+        + " "
+        + &escape_log_str(&entry.uri)
+        + " "
+        + &entry.version
+        + "\" "
+        + &entry.status.to_string()
+        + " "
+        + &bytes
+}
+
+fn get_json_line(entry: &LogEntry, bytes_sent: u64) -> String {
+    let json_entry = JsonLogEntry {
+        time: get_iso_date(entry.time),
+        remote_address: entry.remote_address.map(|addr| addr.ip().to_string()),
+        method: &entry.method,
+        path: &entry.uri,
+        protocol: &entry.version,
+        status: entry.status,
+        bytes_sent,
+        duration_ms: entry.start.elapsed().as_secs_f64() * 1000.0,
+        content_encoding: entry.content_encoding.as_deref(),
+        user_agent: entry.user_agent.as_deref(),
+        referer: entry.referer.as_deref(),
+    };
+
+    serde_json::to_string(&json_entry).unwrap_or_default()
+}
+
+// "Sun, 18 Oct 2026 11:42:10 GMT" -> ["18", "Oct", "2026", "11:42:10"]
+fn get_date_parts(time: SystemTime) -> Vec<String> {
+    httpdate::fmt_http_date(time)
+        .split_whitespace()
+        .skip(1)
+        .take(4)
+        .map(|part| part.to_string())
+        .collect()
+}
+
+// "18/Oct/2026:11:42:10 +0000"
+fn get_clf_date(time: SystemTime) -> String {
+    let parts = get_date_parts(time);
+    match parts.as_slice() {
+        [day, month, year, clock] => format!("{}/{}/{}:{} +0000", day, month, year, clock),
+        _ => "-".to_string(),
+    }
+}
+
+// "2026-10-18T11:42:10Z"
+fn get_iso_date(time: SystemTime) -> String {
+    let parts = get_date_parts(time);
+    let (day, month, year, clock) = match parts.as_slice() {
+        [day, month, year, clock] => (day, month, year, clock),
+        _ => return "-".to_string(),
+    };
+
+    let month_number = match MONTHS.iter().position(|m| m == month) {
+        Some(index) => index + 1,
+        _ => 0,
+    };
+
+    format!("{}-{:02}-{}T{}Z", year, month_number, day, clock)
+}
+
+fn get_header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    match headers.get(name) {
+        Some(value) => value.to_str().ok().map(|v| v.to_string()),
+        _ => None,
+    }
+}
+
+// quotes, backslashes, and control characters can't forge log fields
+fn escape_log_str(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            c if c.is_control() => escaped += &format!("\\x{:02x}", c as u32),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,64 @@
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Body, Frame, SizeHint};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+type OnDrop = Box<dyn FnOnce(u64) + Send + Sync>;
+
+// bytes are counted as frames are sent
+pub struct CountedBody {
+    body: BoxBody<Bytes, io::Error>,
+    bytes_sent: u64,
+    on_drop: Option<OnDrop>,
+}
+
+impl CountedBody {
+    pub fn from(
+        body: BoxBody<Bytes, io::Error>,
+        on_drop: impl FnOnce(u64) + Send + Sync + 'static,
+    ) -> CountedBody {
+        CountedBody {
+            body,
+            bytes_sent: 0,
+            on_drop: Some(Box::new(on_drop)),
+        }
+    }
+}
+
+impl Body for CountedBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.body).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.bytes_sent += data.len() as u64;
+            }
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+// finished, failed, and cancelled responses all receive a count
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        if let Some(on_drop) = self.on_drop.take() {
+            on_drop(self.bytes_sent);
+        }
+    }
+}
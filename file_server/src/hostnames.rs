@@ -0,0 +1,12 @@
+use std::collections::HashMap;
+
+// exact hostnames first, then "*.example.com" for a single label
+// hostname must be lowercase
+pub fn get_by_hostname<'a, T>(hostnames: &'a HashMap<String, T>, hostname: &str) -> Option<&'a T> {
+    if let Some(value) = hostnames.get(hostname) {
+        return Some(value);
+    }
+
+    let (_, parent) = hostname.split_once(".")?;
+    hostnames.get(&("*.".to_string() + parent))
+}
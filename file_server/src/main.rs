@@ -1,13 +1,27 @@
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto::Builder;
+use hyper_util::server::graceful::{GracefulShutdown, Watcher};
 use std::env;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
+use tokio::time::timeout;
 
-use config;
+mod access_log;
+mod counted_body;
+mod hostnames;
+mod metrics;
 mod service;
+mod tls;
 
-use crate::config::Config;
+use config::Config;
+
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// avoids a busy loop when out of file descriptors
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
 
 #[tokio::main]
 async fn main() -> Result<(), String> {
@@ -21,28 +35,221 @@ async fn main() -> Result<(), String> {
         Err(e) => return Err(e.to_string()),
     };
 
-    println!("file_server: {}", conf.host_and_port);
+    let tls_acceptor = match &conf.tls {
+        Some(tls_conf) => match tls::get_tls_acceptor(tls_conf) {
+            Ok(acceptor) => Some(acceptor),
+            Err(e) => return Err(e),
+        },
+        _ => None,
+    };
+
+    match tls_acceptor {
+        Some(_) => println!("file_server: https://{}", conf.host_and_port),
+        _ => println!("file_server: {}", conf.host_and_port),
+    }
+
+    let access_log = match &conf.access_log {
+        Some(access_log_conf) => match access_log::AccessLog::try_from(access_log_conf) {
+            Ok(al) => Some(Arc::new(al)),
+            Err(e) => return Err(e),
+        },
+        _ => None,
+    };
+
+    let metrics = match &conf.metrics {
+        Some(_) => Some(Arc::new(metrics::Metrics::new())),
+        _ => None,
+    };
+
+    let mut svc = service::Svc::from(conf.clone());
+    if let Some(al) = &access_log {
+        #[cfg(unix)]
+        tokio::task::spawn(watch_user_signal(al.clone()));
+
+        svc.set_access_log(al.clone());
+    }
+
+    if let (Some(metrics_conf), Some(m)) = (&conf.metrics, &metrics) {
+        let metrics_path = metrics::get_metrics_path(&metrics_conf.path);
+
+        match &metrics_conf.host_and_port {
+            Some(admin_host_and_port) => {
+                let admin_listener = match TcpListener::bind(admin_host_and_port).await {
+                    Ok(lstnr) => lstnr,
+                    Err(e) => return Err(e.to_string()),
+                };
+
+                println!(
+                    "file_server: metrics {}{}",
+                    admin_host_and_port, metrics_path
+                );
+
+                let metrics_svc = metrics::MetricsSvc::from(m.clone(), metrics_path);
+                tokio::task::spawn(serve_metrics(admin_listener, metrics_svc));
+                svc.set_metrics(m.clone(), None);
+            }
+            _ => svc.set_metrics(m.clone(), Some(metrics_path)),
+        }
+    }
 
-    let svc = service::Svc::from(conf);
+    let shutdown_timeout = conf.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+    let graceful = GracefulShutdown::new();
+
+    let shutdown_signal = get_shutdown_signal();
+    tokio::pin!(shutdown_signal);
 
     loop {
-        let (stream, _remote_address) = match listener.accept().await {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut shutdown_signal => break,
+        };
+
+        // failed accepts never stop the server
+        let (stream, remote_address) = match accepted {
             Ok(strm) => strm,
-            Err(e) => return Err(e.to_string()),
+            Err(e) => {
+                eprintln!("file_server: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
         };
 
-        let io = TokioIo::new(stream);
-        let svc = svc.clone();
+        let mut svc = svc.clone();
+        svc.set_remote_address(remote_address);
+        let tls_acceptor = tls_acceptor.clone();
+        let connection = metrics.as_ref().map(|m| m.open_connection());
+        let watcher = graceful.watcher();
+
+        tokio::task::spawn(async move {
+            let _connection = connection;
+
+            match tls_acceptor {
+                Some(acceptor) => {
+                    // clients that never finish a handshake are dropped
+                    match timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(tls_stream)) => serve_connection(tls_stream, svc, watcher).await,
+                        Ok(Err(e)) => {
+                            eprintln!("file_server: {}: tls handshake: {}", remote_address, e)
+                        }
+                        _ => eprintln!("file_server: {}: tls handshake timed out", remote_address),
+                    }
+                }
+                _ => serve_connection(stream, svc, watcher).await,
+            }
+        });
+    }
+
+    // stop accepting and let in-flight responses finish
+    drop(listener);
+    println!("file_server: shutting down");
+
+    let deadline = Duration::from_secs(shutdown_timeout);
+    if timeout(deadline, graceful.shutdown()).await.is_err() {
+        eprintln!("file_server: shutdown timeout reached, closing remaining connections");
+    }
+
+    if let Some(al) = access_log {
+        let _ = tokio::task::spawn_blocking(move || al.flush()).await;
+    }
+
+    Ok(())
+}
+
+// SIGTERM or ctrl-c
+async fn get_shutdown_signal() {
+    let interrupt = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(e) => {
+                eprintln!("file_server: SIGTERM unavailable: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+}
+
+async fn serve_connection<S>(stream: S, svc: service::Svc, watcher: Watcher)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(stream);
+    let remote_address = svc.get_remote_address();
+    let log_errors = svc.has_access_log();
+
+    let builder = Builder::new(TokioExecutor::new());
+    let connection = builder.serve_connection(io, svc);
 
+    // connection errors are logged alongside requests
+    if let Err(e) = watcher.watch(connection).await {
+        if log_errors {
+            match remote_address {
+                Some(addr) => eprintln!("file_server: {}: {}", addr, e),
+                _ => eprintln!("file_server: {}", e),
+            }
+        }
+    }
+}
+
+// the admin listener serves plain http
+async fn serve_metrics(listener: TcpListener, metrics_svc: metrics::MetricsSvc) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((strm, _)) => strm,
+            Err(e) => {
+                eprintln!("file_server: metrics listener: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+
+        let metrics_svc = metrics_svc.clone();
         tokio::task::spawn(async move {
-            // log service errors here
-            Builder::new(TokioExecutor::new())
-                .serve_connection(io, svc)
-                .await
+            let _ = Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), metrics_svc)
+                .await;
         });
     }
 }
 
+// reopen the access log on SIGUSR1
+#[cfg(unix)]
+async fn watch_user_signal(access_log: Arc<access_log::AccessLog>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut user_signal = match signal(SignalKind::user_defined1()) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("file_server: SIGUSR1 unavailable: {}", e);
+            return;
+        }
+    };
+
+    while user_signal.recv().await.is_some() {
+        if let Err(e) = access_log.reopen() {
+            eprintln!("file_server: access log not reopened: {}", e);
+        }
+    }
+}
+
 async fn get_config() -> Result<Config, String> {
     match env::args().nth(1) {
         Some(conf_path_arg) => {
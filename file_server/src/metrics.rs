@@ -0,0 +1,271 @@
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming as IncomingBody;
+use hyper::header::{HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, RANGE};
+use hyper::http::{Method, Request, Response, StatusCode};
+use hyper::service::Service;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use response::BoxedResponse;
+
+use crate::counted_body::CountedBody;
+
+// https://prometheus.io/docs/instrumenting/exposition_formats/
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4; charset=utf-8";
+const DEFAULT_PATH: &str = "/metrics";
+// seconds, the prometheus client defaults
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug)]
+pub struct Metrics {
+    active_connections: AtomicU64,
+    counters: Mutex<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    // (method, status)
+    requests: BTreeMap<(String, u16), u64>,
+    bytes_sent: u64,
+    encodings: BTreeMap<String, u64>,
+    // status of requests with a range header
+    range_requests: BTreeMap<u16, u64>,
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+    latency_count: u64,
+}
+
+// the start time, method label, and whether a range was requested
+pub struct MetricsEntry {
+    start: Instant,
+    method: String,
+    range: bool,
+}
+
+// the connection is counted until dropped
+pub struct ConnectionGuard {
+    metrics: Arc<Metrics>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            active_connections: AtomicU64::new(0),
+            counters: Mutex::new(Counters::default()),
+        }
+    }
+
+    pub fn open_connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            metrics: self.clone(),
+        }
+    }
+
+    fn record(&self, entry: &MetricsEntry, status: u16, encoding: Option<String>, bytes: u64) {
+        let seconds = entry.start.elapsed().as_secs_f64();
+
+        let mut counters = match self.counters.lock() {
+            Ok(c) => c,
+            _ => return,
+        };
+
+        *counters
+            .requests
+            .entry((entry.method.clone(), status))
+            .or_insert(0) += 1;
+        counters.bytes_sent += bytes;
+
+        if let Some(enc) = encoding {
+            *counters.encodings.entry(enc).or_insert(0) += 1;
+        }
+
+        if entry.range {
+            *counters.range_requests.entry(status).or_insert(0) += 1;
+        }
+
+        for (index, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if seconds <= *bound {
+                counters.latency_buckets[index] += 1;
+            }
+        }
+        counters.latency_sum += seconds;
+        counters.latency_count += 1;
+    }
+
+    pub fn build_response(&self) -> Result<BoxedResponse, hyper::http::Error> {
+        let body = self.get_text();
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, HeaderValue::from_static(TEXT_FORMAT))
+            .body(Full::new(Bytes::from(body)).map_err(|e| match e {}).boxed())
+    }
+
+    fn get_text(&self) -> String {
+        let active_connections = self.active_connections.load(Ordering::Relaxed);
+
+        let counters = match self.counters.lock() {
+            Ok(c) => c,
+            _ => return "".to_string(),
+        };
+
+        let mut text = String::new();
+
+        text += "# HELP file_server_requests_total Requests by method and status.\n";
+        text += "# TYPE file_server_requests_total counter\n";
+        for ((method, status), count) in &counters.requests {
+            text += &format!(
+                "file_server_requests_total{{method=\"{}\",status=\"{}\"}} {}\n",
+                method, status, count
+            );
+        }
+
+        text += "# HELP file_server_sent_bytes_total Response body bytes sent.\n";
+        text += "# TYPE file_server_sent_bytes_total counter\n";
+        text += &format!("file_server_sent_bytes_total {}\n", counters.bytes_sent);
+
+        text += "# HELP file_server_encoded_responses_total Responses by content encoding.\n";
+        text += "# TYPE file_server_encoded_responses_total counter\n";
+        for (encoding, count) in &counters.encodings {
+            text += &format!(
+                "file_server_encoded_responses_total{{encoding=\"{}\"}} {}\n",
+                encoding, count
+            );
+        }
+
+        text += "# HELP file_server_range_requests_total Range requests by status.\n";
+        text += "# TYPE file_server_range_requests_total counter\n";
+        for (status, count) in &counters.range_requests {
+            text += &format!(
+                "file_server_range_requests_total{{status=\"{}\"}} {}\n",
+                status, count
+            );
+        }
+
+        text += "# HELP file_server_active_connections Open client connections.\n";
+        text += "# TYPE file_server_active_connections gauge\n";
+        text += &format!("file_server_active_connections {}\n", active_connections);
+
+        text += "# HELP file_server_request_duration_seconds Time until a response body is sent.\n";
+        text += "# TYPE file_server_request_duration_seconds histogram\n";
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(counters.latency_buckets) {
+            text += &format!(
+                "file_server_request_duration_seconds_bucket{{le=\"{}\"}} {}\n",
+                bound, count
+            );
+        }
+        text += &format!(
+            "file_server_request_duration_seconds_bucket{{le=\"+Inf\"}} {}\n",
+            counters.latency_count
+        );
+        text += &format!(
+            "file_server_request_duration_seconds_sum {}\n",
+            counters.latency_sum
+        );
+        text += &format!(
+            "file_server_request_duration_seconds_count {}\n",
+            counters.latency_count
+        );
+
+        text
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl MetricsEntry {
+    pub fn from(req: &Request<IncomingBody>) -> MetricsEntry {
+        MetricsEntry {
+            start: Instant::now(),
+            method: get_method_label(req.method()),
+            range: req.headers().contains_key(RANGE),
+        }
+    }
+}
+
+pub fn meter_response(
+    metrics: Arc<Metrics>,
+    entry: MetricsEntry,
+    response: BoxedResponse,
+) -> BoxedResponse {
+    let status = response.status().as_u16();
+    // precompressed and dynamically compressed files
+    let encoding = match response.headers().get(CONTENT_ENCODING) {
+        Some(value) => value.to_str().ok().map(|v| v.to_ascii_lowercase()),
+        _ => None,
+    };
+
+    response.map(|body| {
+        CountedBody::from(body, move |bytes_sent| {
+            metrics.record(&entry, status, encoding, bytes_sent)
+        })
+        .boxed()
+    })
+}
+
+pub fn get_metrics_path(path: &Option<String>) -> String {
+    match path {
+        Some(p) => p.clone(),
+        _ => DEFAULT_PATH.to_string(),
+    }
+}
+
+// arbitrary methods would create unbounded labels
+fn get_method_label(method: &Method) -> String {
+    match *method {
+        Method::GET
+        | Method::HEAD
+        | Method::POST
+        | Method::PUT
+        | Method::DELETE
+        | Method::CONNECT
+        | Method::OPTIONS
+        | Method::TRACE
+        | Method::PATCH => method.to_string(),
+        _ => "OTHER".to_string(),
+    }
+}
+
+// serves only metrics on a separate admin address
+#[derive(Clone, Debug)]
+pub struct MetricsSvc {
+    metrics: Arc<Metrics>,
+    path: String,
+}
+
+impl MetricsSvc {
+    pub fn from(metrics: Arc<Metrics>, path: String) -> MetricsSvc {
+        MetricsSvc { metrics, path }
+    }
+}
+
+impl Service<Request<IncomingBody>> for MetricsSvc {
+    type Response = BoxedResponse;
+    type Error = hyper::http::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&self, req: Request<IncomingBody>) -> Self::Future {
+        let res = match req.uri().path() == self.path {
+            true => self.metrics.build_response(),
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::new(Bytes::new()).map_err(|e| match e {}).boxed()),
+        };
+
+        Box::pin(async move { res })
+    }
+}
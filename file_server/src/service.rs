@@ -1,9 +1,17 @@
 use hyper::body::Incoming as IncomingBody;
+use hyper::header::HOST;
+use hyper::http::uri::Authority;
 use hyper::service::Service;
-use hyper::Request;
+use hyper::{Method, Request};
+use std::collections::HashMap;
 use std::future::Future;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::Arc;
 
+use crate::access_log::{log_response, AccessLog, LogEntry};
+use crate::hostnames::get_by_hostname;
+use crate::metrics::{meter_response, Metrics, MetricsEntry};
 use config::Config;
 /*
     BoxedResponse is a type.
@@ -15,16 +23,81 @@ use response::{build_response, BoxedResponse, ResponseParams};
 #[derive(Clone, Debug)]
 pub struct Svc {
     response_params: ResponseParams,
+    sites: Arc<HashMap<String, ResponseParams>>,
+    access_log: Option<Arc<AccessLog>>,
+    metrics: Option<Arc<Metrics>>,
+    metrics_path: Option<String>,
+    remote_address: Option<SocketAddr>,
 }
 
 impl Svc {
     pub fn from(config: Config) -> Svc {
+        let response_params = ResponseParams::from(config.clone());
+
+        let mut sites = HashMap::new();
+        if let Some(site_configs) = &config.sites {
+            for site in site_configs {
+                let site_params =
+                    ResponseParams::from_site(config.get_site_config(site), &response_params);
+
+                for hostname in &site.hostnames {
+                    sites.insert(hostname.to_ascii_lowercase(), site_params.clone());
+                }
+            }
+        }
+
         Svc {
-            response_params: ResponseParams::from(
-                config.directory,
-                config.filepath_404,
-                config.content_encodings,
-            ),
+            response_params,
+            sites: Arc::new(sites),
+            access_log: None,
+            metrics: None,
+            metrics_path: None,
+            remote_address: None,
+        }
+    }
+
+    pub fn set_access_log(&mut self, access_log: Arc<AccessLog>) {
+        self.access_log = Some(access_log);
+    }
+
+    // metrics are served on the main listener when given a path
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>, metrics_path: Option<String>) {
+        self.metrics = Some(metrics);
+        self.metrics_path = metrics_path;
+    }
+
+    // each connection gets its own service
+    pub fn set_remote_address(&mut self, remote_address: SocketAddr) {
+        self.remote_address = Some(remote_address);
+    }
+
+    pub fn get_remote_address(&self) -> Option<SocketAddr> {
+        self.remote_address
+    }
+
+    pub fn has_access_log(&self) -> bool {
+        self.access_log.is_some()
+    }
+
+    fn get_metrics_page(&self, req: &Request<IncomingBody>) -> Option<Arc<Metrics>> {
+        let metrics_path = self.metrics_path.as_ref()?;
+        if Method::GET != req.method() || req.uri().path() != metrics_path {
+            return None;
+        }
+
+        self.metrics.clone()
+    }
+
+    // requests without a known host are served by the top level config
+    fn get_response_params(&self, req: &Request<IncomingBody>) -> &ResponseParams {
+        let host = match get_host(req) {
+            Some(h) => h,
+            _ => return &self.response_params,
+        };
+
+        match get_by_hostname(&self.sites, &host) {
+            Some(site_params) => site_params,
+            _ => &self.response_params,
         }
     }
 }
@@ -35,8 +108,49 @@ impl Service<Request<IncomingBody>> for Svc {
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn call(&self, req: Request<IncomingBody>) -> Self::Future {
-        let response_params = self.response_params.clone();
+        let response_params = self.get_response_params(&req).clone();
+        let metrics_page = self.get_metrics_page(&req);
+
+        let log_entry = self
+            .access_log
+            .as_ref()
+            .map(|al| (al.clone(), LogEntry::from(&req, self.remote_address)));
+
+        let metrics_entry = self
+            .metrics
+            .as_ref()
+            .map(|m| (m.clone(), MetricsEntry::from(&req)));
 
-        Box::pin(async move { build_response(req, response_params).await })
+        Box::pin(async move {
+            let mut res = match metrics_page {
+                Some(metrics) => metrics.build_response(),
+                _ => build_response(req, response_params).await,
+            };
+
+            if let Some((metrics, entry)) = metrics_entry {
+                res = res.map(|response| meter_response(metrics, entry, response));
+            }
+
+            if let Some((access_log, entry)) = log_entry {
+                res = res.map(|response| log_response(access_log, entry, response));
+            }
+
+            res
+        })
+    }
+}
+
+// http2 sends an :authority, http1 sends a Host header
+fn get_host(req: &Request<IncomingBody>) -> Option<String> {
+    if let Some(authority) = req.uri().authority() {
+        return Some(authority.host().to_ascii_lowercase());
     }
+
+    let host_header = req.headers().get(HOST)?.to_str().ok()?;
+    let authority: Authority = match host_header.parse() {
+        Ok(a) => a,
+        _ => return None,
+    };
+
+    Some(authority.host().to_ascii_lowercase())
 }
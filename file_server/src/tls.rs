@@ -0,0 +1,205 @@
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+use crate::hostnames::get_by_hostname;
+use config::TlsConfig;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+// certificates are swapped in place so new handshakes pick up
+// reloaded files without rebuilding the acceptor
+#[derive(Debug)]
+struct CertResolver {
+    certificates: RwLock<Certificates>,
+}
+
+#[derive(Debug)]
+struct Certificates {
+    default: Arc<CertifiedKey>,
+    hostnames: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    // clients without a known sni hostname get the default certificate
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let certificates = match self.certificates.read() {
+            Ok(c) => c,
+            _ => return None,
+        };
+
+        if let Some(server_name) = client_hello.server_name() {
+            if let Some(certified_key) = certificates.get(server_name) {
+                return Some(certified_key);
+            }
+        }
+
+        Some(certificates.default.clone())
+    }
+}
+
+impl Certificates {
+    fn try_from(tls: &TlsConfig, provider: &CryptoProvider) -> Result<Certificates, String> {
+        let default = get_certified_key(&tls.cert_filepath, &tls.key_filepath, provider)?;
+
+        let mut hostnames = HashMap::new();
+        if let Some(certificates) = &tls.certificates {
+            for certificate in certificates {
+                let certified_key = get_certified_key(
+                    &certificate.cert_filepath,
+                    &certificate.key_filepath,
+                    provider,
+                )?;
+
+                for hostname in &certificate.hostnames {
+                    hostnames.insert(hostname.to_ascii_lowercase(), certified_key.clone());
+                }
+            }
+        }
+
+        Ok(Certificates { default, hostnames })
+    }
+
+    fn get(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        get_by_hostname(&self.hostnames, &server_name.to_ascii_lowercase()).cloned()
+    }
+}
+
+pub fn get_tls_acceptor(tls: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let provider = Arc::new(ring::default_provider());
+    let certificates = Certificates::try_from(tls, &provider)?;
+    let resolver = Arc::new(CertResolver {
+        certificates: RwLock::new(certificates),
+    });
+
+    let mut server_config = match ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+    {
+        Ok(b) => b.with_no_client_auth().with_cert_resolver(resolver.clone()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    // alpn lets clients negotiate http2 over tls
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    tokio::task::spawn(watch_files(resolver.clone(), provider.clone(), tls.clone()));
+    #[cfg(unix)]
+    tokio::task::spawn(watch_hangup(resolver, provider, tls.clone()));
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+// reload when a certificate or key file is modified
+async fn watch_files(resolver: Arc<CertResolver>, provider: Arc<CryptoProvider>, tls: TlsConfig) {
+    let mut modified = get_modified_times(&tls).await;
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let current = get_modified_times(&tls).await;
+        if current == modified {
+            continue;
+        }
+        modified = current;
+
+        reload_certificates(&resolver, &provider, &tls);
+    }
+}
+
+// reload on SIGHUP
+#[cfg(unix)]
+async fn watch_hangup(resolver: Arc<CertResolver>, provider: Arc<CryptoProvider>, tls: TlsConfig) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("file_server: SIGHUP unavailable: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        reload_certificates(&resolver, &provider, &tls);
+    }
+}
+
+// a failed reload keeps the current certificates
+fn reload_certificates(resolver: &CertResolver, provider: &CryptoProvider, tls: &TlsConfig) {
+    let certificates = match Certificates::try_from(tls, provider) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("file_server: certificates not reloaded: {}", e);
+            return;
+        }
+    };
+
+    if let Ok(mut current) = resolver.certificates.write() {
+        *current = certificates;
+        println!("file_server: certificates reloaded");
+    }
+}
+
+fn get_certified_key(
+    cert_filepath: &Path,
+    key_filepath: &Path,
+    provider: &CryptoProvider,
+) -> Result<Arc<CertifiedKey>, String> {
+    let mut certs = Vec::new();
+    let cert_iter = match CertificateDer::pem_file_iter(cert_filepath) {
+        Ok(ci) => ci,
+        Err(e) => return Err(cert_filepath.display().to_string() + ": " + &e.to_string()),
+    };
+
+    for cert in cert_iter {
+        match cert {
+            Ok(c) => certs.push(c),
+            Err(e) => return Err(cert_filepath.display().to_string() + ": " + &e.to_string()),
+        }
+    }
+
+    if certs.is_empty() {
+        return Err(cert_filepath.display().to_string() + ": no certificates found");
+    }
+
+    let key = match PrivateKeyDer::from_pem_file(key_filepath) {
+        Ok(k) => k,
+        Err(e) => return Err(key_filepath.display().to_string() + ": " + &e.to_string()),
+    };
+
+    match CertifiedKey::from_der(certs, key, provider) {
+        Ok(ck) => Ok(Arc::new(ck)),
+        Err(e) => Err(cert_filepath.display().to_string() + ": " + &e.to_string()),
+    }
+}
+
+async fn get_modified_times(tls: &TlsConfig) -> Vec<Option<SystemTime>> {
+    let mut filepaths = vec![&tls.cert_filepath, &tls.key_filepath];
+    if let Some(certificates) = &tls.certificates {
+        for certificate in certificates {
+            filepaths.push(&certificate.cert_filepath);
+            filepaths.push(&certificate.key_filepath);
+        }
+    }
+
+    let mut modified_times = Vec::new();
+    for filepath in filepaths {
+        let modified = match fs::metadata(filepath).await {
+            Ok(m) => m.modified().ok(),
+            _ => None,
+        };
+        modified_times.push(modified);
+    }
+
+    modified_times
+}
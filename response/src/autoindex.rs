@@ -0,0 +1,228 @@
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use hyper::http::{Request, Response};
+use hyper::StatusCode;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+use tokio::fs;
+
+use crate::available_encodings::get_encoded_ext;
+use crate::content_type::{HTML, JSON};
+use crate::response_paths::get_directory_from_url_path;
+use crate::type_flyweight::{BoxedResponse, ResponseParams};
+use crate::url_paths::{encode_url_path, percent_encode};
+use crate::validators::get_last_modified;
+
+#[derive(Serialize)]
+struct Listing {
+    path: String,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    name: String,
+    directory: bool,
+    size: Option<u64>,
+    modified: Option<String>,
+}
+
+// directories without an index file are listed when autoindex is enabled
+pub async fn build_response(
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
+    url_path: &str,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    if !res_params.autoindex {
+        return None;
+    }
+
+    let dirpath = get_directory_from_url_path(url_path, res_params).await?;
+
+    let mut path = url_path.to_string();
+    if !path.ends_with("/") {
+        path.push('/');
+    }
+
+    let listing = Listing {
+        path,
+        entries: get_entries(res_params, &dirpath).await?,
+    };
+
+    let (content_type, body) = match accepts_json(req) {
+        true => match serde_json::to_string(&listing) {
+            Ok(json) => (JSON, json),
+            _ => return None,
+        },
+        _ => (HTML, build_html(&listing)),
+    };
+
+    Some(
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, content_type)
+            .header(CONTENT_LENGTH, body.len())
+            .header(VARY, "accept")
+            .body(Full::new(Bytes::from(body)).map_err(|e| match e {}).boxed()),
+    )
+}
+
+// entries follow the same path policy as served files
+// directories come first, then files by name
+async fn get_entries(res_params: &ResponseParams, dirpath: &Path) -> Option<Vec<Entry>> {
+    let mount = res_params.mounts.get_file_mount(dirpath)?;
+    let mut read_dir = match fs::read_dir(dirpath).await {
+        Ok(rd) => rd,
+        _ => return None,
+    };
+
+    let mut entries = Vec::new();
+    while let Ok(Some(dir_entry)) = read_dir.next_entry().await {
+        let entry_path = dir_entry.path();
+
+        // names that can't be requested are not listed
+        let name = match dir_entry.file_name().into_string() {
+            Ok(n) => n,
+            _ => continue,
+        };
+
+        if res_params
+            .path_policy
+            .is_denied(&res_params.mounts.get_url_path(&entry_path))
+        {
+            continue;
+        }
+
+        if !res_params.path_policy.allows(mount, &entry_path).await {
+            continue;
+        }
+
+        let metadata = match fs::metadata(&entry_path).await {
+            Ok(m) => m,
+            _ => continue,
+        };
+
+        entries.push(Entry {
+            name,
+            directory: metadata.is_dir(),
+            size: match metadata.is_file() {
+                true => Some(metadata.len()),
+                _ => None,
+            },
+            modified: get_last_modified(&metadata),
+        });
+    }
+
+    let mut entries = remove_encoded_siblings(res_params, entries);
+    entries.sort_by(|a, b| b.directory.cmp(&a.directory).then(a.name.cmp(&b.name)));
+
+    Some(entries)
+}
+
+// "index.html.zst" is hidden when "index.html" is listed
+fn remove_encoded_siblings(res_params: &ResponseParams, entries: Vec<Entry>) -> Vec<Entry> {
+    let filenames: HashSet<String> = entries
+        .iter()
+        .filter(|entry| !entry.directory)
+        .map(|entry| entry.name.clone())
+        .collect();
+
+    let mut exts = Vec::new();
+    for encoding in res_params.available_encodings.get_encodings() {
+        if let Some(ext) = get_encoded_ext(encoding) {
+            exts.push(ext);
+        }
+    }
+
+    entries
+        .into_iter()
+        .filter(|entry| {
+            entry.directory
+                || !exts.iter().any(|ext| match entry.name.strip_suffix(ext) {
+                    Some(original) => filenames.contains(original),
+                    _ => false,
+                })
+        })
+        .collect()
+}
+
+fn accepts_json(req: &Request<Incoming>) -> bool {
+    match req.headers().get(ACCEPT) {
+        Some(accept) => match accept.to_str() {
+            Ok(a) => a.contains("application/json"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn build_html(listing: &Listing) -> String {
+    let title = "Index of ".to_string() + &escape_html(&listing.path);
+
+    let mut html = "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n".to_string();
+    html += &("<title>".to_string() + &title + "</title>\n</head>\n<body>\n");
+    html += &("<h1>".to_string() + &title + "</h1>\n<table>\n");
+    html += "<tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n";
+
+    let encoded_path = encode_url_path(&listing.path);
+
+    if let Some(parent) = get_parent_path(&listing.path) {
+        html += &("<tr><td><a href=\"".to_string()
+            + &encode_url_path(parent)
+            + "\">../</a></td><td></td><td></td></tr>\n");
+    }
+
+    for entry in &listing.entries {
+        let mut href = encoded_path.clone() + &percent_encode(&entry.name);
+        let mut name = escape_html(&entry.name);
+        if entry.directory {
+            href.push('/');
+            name.push('/');
+        }
+
+        let size = match entry.size {
+            Some(s) => s.to_string(),
+            _ => "-".to_string(),
+        };
+
+        html += &("<tr><td><a href=\"".to_string()
+            + &href
+            + "\">"
+            + &name
+            + "</a></td><td>"
+            + &size
+            + "</td><td>"
+            + entry.modified.as_deref().unwrap_or("")
+            + "</td></tr>\n");
+    }
+
+    html += "</table>\n</body>\n</html>\n";
+    html
+}
+
+// "/docs/guides/" -> "/docs/"
+fn get_parent_path(path: &str) -> Option<&str> {
+    let trimmed = path.strip_suffix("/")?;
+    let (parent, _) = trimmed.rsplit_once("/")?;
+
+    Some(&path[..parent.len() + 1])
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
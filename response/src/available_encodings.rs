@@ -1,28 +1,20 @@
+// encodings are kept in configured order, earlier encodings are preferred
 #[derive(Clone, Debug)]
 pub struct AvailableEncodings {
-    gzip: bool,
-    deflate: bool,
-    br: bool,
-    zstd: bool,
+    encodings: Vec<String>,
 }
 
 impl AvailableEncodings {
     pub fn from(potential_encodings: Option<Vec<String>>) -> AvailableEncodings {
         let mut av_enc = AvailableEncodings {
-            gzip: false,
-            deflate: false,
-            br: false,
-            zstd: false,
+            encodings: Vec::new(),
         };
 
         if let Some(encodings) = potential_encodings {
             for encoding in encodings {
-                match encoding.as_str() {
-                    "gzip" => av_enc.gzip = true,
-                    "deflate" => av_enc.deflate = true,
-                    "br" => av_enc.br = true,
-                    "zstd" => av_enc.zstd = true,
-                    _ => {}
+                if get_encoded_ext(&encoding).is_some() && !av_enc.encoding_is_available(&encoding)
+                {
+                    av_enc.encodings.push(encoding);
                 }
             }
         }
@@ -31,13 +23,11 @@ impl AvailableEncodings {
     }
 
     pub fn encoding_is_available(&self, encoding: &str) -> bool {
-        match encoding {
-            "gzip" => self.gzip,
-            "deflate" => self.deflate,
-            "br" => self.br,
-            "zstd" => self.zstd,
-            _ => false,
-        }
+        self.encodings.iter().any(|enc| enc == encoding)
+    }
+
+    pub fn get_encodings(&self) -> &Vec<String> {
+        &self.encodings
     }
 }
 
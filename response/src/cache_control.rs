@@ -0,0 +1,36 @@
+use config::CacheControlRule;
+use hyper::header::HeaderValue;
+
+use crate::path_patterns::pattern_matches;
+
+#[derive(Clone, Debug)]
+pub struct CacheControlRules {
+    rules: Vec<(String, HeaderValue)>,
+}
+
+impl CacheControlRules {
+    pub fn from(potential_rules: Option<Vec<CacheControlRule>>) -> CacheControlRules {
+        let mut cc_rules = CacheControlRules { rules: Vec::new() };
+
+        if let Some(rules) = potential_rules {
+            for rule in rules {
+                if let Ok(value) = HeaderValue::from_str(&rule.value) {
+                    cc_rules.rules.push((rule.pattern, value));
+                }
+            }
+        }
+
+        cc_rules
+    }
+
+    // first matching rule wins
+    pub fn get_cache_control(&self, url_path: &str) -> Option<&HeaderValue> {
+        for (pattern, value) in &self.rules {
+            if pattern_matches(pattern, url_path) {
+                return Some(value);
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,145 @@
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+use async_compression::Level;
+use bytes::Bytes;
+use config::CompressionConfig;
+use futures_util::TryStreamExt;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use std::pin::Pin;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, BufReader};
+use tokio_util::io::ReaderStream;
+
+use crate::compression_cache::CompressionCache;
+
+const DEFAULT_MIN_SIZE: u64 = 1024;
+const DEFAULT_CONTENT_TYPES: [&str; 10] = [
+    "text/html",
+    "text/css",
+    "text/csv",
+    "text/javascript",
+    "text/plain",
+    "application/json",
+    "application/manifest+json",
+    "application/wasm",
+    "application/xml",
+    "image/svg+xml",
+];
+
+// on-the-fly compression is only enabled when configured
+#[derive(Clone, Debug)]
+pub struct Compression {
+    enabled: bool,
+    min_size: u64,
+    content_types: Vec<String>,
+    pub cache: CompressionCache,
+}
+
+impl Compression {
+    pub fn from(potential_compression: Option<CompressionConfig>) -> Compression {
+        let compression = match potential_compression {
+            Some(c) => c,
+            _ => {
+                return Compression {
+                    enabled: false,
+                    min_size: DEFAULT_MIN_SIZE,
+                    content_types: Vec::new(),
+                    cache: CompressionCache::from(None, None),
+                }
+            }
+        };
+
+        let content_types = match compression.content_types {
+            Some(cts) => cts,
+            _ => DEFAULT_CONTENT_TYPES
+                .iter()
+                .map(|ct| ct.to_string())
+                .collect(),
+        };
+
+        Compression {
+            enabled: true,
+            min_size: compression.min_size.unwrap_or(DEFAULT_MIN_SIZE),
+            content_types,
+            cache: CompressionCache::from(compression.cache_directory, compression.cache_max_size),
+        }
+    }
+
+    // content types are compared without parameters like "charset"
+    // "text/*" matches every text type
+    pub fn is_compressible(&self, content_type: &str, size: u64) -> bool {
+        if !self.enabled || size < self.min_size {
+            return false;
+        }
+
+        let essence = match content_type.split_once(";") {
+            Some((e, _)) => e.trim(),
+            _ => content_type,
+        };
+
+        for allowed in &self.content_types {
+            if let Some(prefix) = allowed.strip_suffix("*") {
+                if essence.starts_with(prefix) {
+                    return true;
+                }
+            }
+
+            if allowed == essence {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // first negotiated encoding that can be produced on the fly
+    pub fn get_encoding<'a>(
+        &self,
+        content_type: &str,
+        size: u64,
+        encodings: &'a Option<Vec<String>>,
+    ) -> Option<&'a str> {
+        if !self.is_compressible(content_type, size) {
+            return None;
+        }
+
+        let encds = match encodings {
+            Some(encds) => encds,
+            _ => return None,
+        };
+
+        for enc in encds {
+            if let "gzip" | "br" | "zstd" = enc.as_str() {
+                return Some(enc);
+            }
+        }
+
+        None
+    }
+}
+
+pub type Encoder = Pin<Box<dyn AsyncRead + Send + Sync>>;
+
+pub fn get_encoder(file: File, encoding: &str) -> Option<Encoder> {
+    let reader = BufReader::new(file);
+
+    match encoding {
+        "gzip" => Some(Box::pin(GzipEncoder::new(reader))),
+        // brotli's default quality is too slow for responses
+        "br" => Some(Box::pin(BrotliEncoder::with_quality(
+            reader,
+            Level::Precise(4),
+        ))),
+        "zstd" => Some(Box::pin(ZstdEncoder::new(reader))),
+        _ => None,
+    }
+}
+
+pub fn build_compressed_body(file: File, encoding: &str) -> Option<BoxBody<Bytes, std::io::Error>> {
+    let encoder = get_encoder(file, encoding)?;
+    let reader_stream = ReaderStream::new(encoder);
+    let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
+
+    Some(stream_body.boxed())
+}
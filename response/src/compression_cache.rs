@@ -0,0 +1,435 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+use tokio::io;
+use tokio::io::AsyncWriteExt;
+
+use crate::available_encodings::get_encoded_ext;
+use crate::compression::get_encoder;
+
+const DEFAULT_MAX_SIZE: u64 = 256 * 1024 * 1024;
+const ENCODINGS: [&str; 4] = ["gzip", "zstd", "br", "deflate"];
+
+static TEMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// compressed files are written to a cache directory and served like
+// precompressed siblings on later requests
+//
+// entries are keyed by source path, mtime, size, and encoding
+// so a changed source file never hits a stale entry
+#[derive(Clone, Debug)]
+pub struct CompressionCache {
+    directory: Option<PathBuf>,
+    max_size: u64,
+    entries: Arc<Mutex<CacheEntries>>,
+}
+
+#[derive(Debug, Default)]
+struct CacheEntries {
+    // filename -> (size, last used)
+    files: HashMap<String, (u64, u64)>,
+    // (source path, encoding) -> filename
+    sources: HashMap<(PathBuf, String), String>,
+    pending: HashSet<String>,
+    total_size: u64,
+    tick: u64,
+}
+
+impl CompressionCache {
+    pub fn from(directory: Option<PathBuf>, max_size: Option<u64>) -> CompressionCache {
+        let max_size = max_size.unwrap_or(DEFAULT_MAX_SIZE);
+
+        // files from previous runs count towards the max size
+        let mut entries = CacheEntries::default();
+        if let Some(dir) = &directory {
+            let victims = scan_cache_directory(dir, max_size, &mut entries);
+            for filename in victims {
+                let _ = std::fs::remove_file(dir.join(filename));
+            }
+        }
+
+        CompressionCache {
+            directory,
+            max_size,
+            entries: Arc::new(Mutex::new(entries)),
+        }
+    }
+
+    pub async fn get_cached_path(
+        &self,
+        filepath: &Path,
+        metadata: &Metadata,
+        encoding: &str,
+    ) -> Option<PathBuf> {
+        let directory = self.directory.as_ref()?;
+        let filename = get_cache_filename(filepath, metadata, encoding)?;
+        let cached_path = directory.join(&filename);
+
+        // entries from a previous run are adopted
+        let cached_metadata = match fs::metadata(&cached_path).await {
+            Ok(m) => m,
+            _ => {
+                self.remove_entry(&filename);
+                return None;
+            }
+        };
+
+        let mut entries = match self.entries.lock() {
+            Ok(e) => e,
+            _ => return None,
+        };
+
+        entries.tick += 1;
+        let tick = entries.tick;
+        match entries.files.get_mut(&filename) {
+            Some((_, last_used)) => *last_used = tick,
+            _ => {
+                entries.total_size += cached_metadata.len();
+                entries
+                    .files
+                    .insert(filename.clone(), (cached_metadata.len(), tick));
+            }
+        }
+
+        Some(cached_path)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.directory.is_some()
+    }
+
+    // compression happens in the background, the current request is streamed
+    pub fn insert(&self, filepath: &Path, metadata: &Metadata, encoding: &str, file: fs::File) {
+        let directory = match &self.directory {
+            Some(d) => d.clone(),
+            _ => return,
+        };
+
+        let filename = match get_cache_filename(filepath, metadata, encoding) {
+            Some(f) => f,
+            _ => return,
+        };
+
+        if let Ok(mut entries) = self.entries.lock() {
+            if !entries.pending.insert(filename.clone()) {
+                return;
+            }
+        }
+
+        let cache = self.clone();
+        let filepath = filepath.to_path_buf();
+        let encoding = encoding.to_string();
+
+        tokio::task::spawn(async move {
+            let result = write_cache_file(&directory, file, &filename, &encoding).await;
+            let victims = cache.finish_entry(filepath, encoding, filename, result.ok());
+
+            // files are removed after the lock is released
+            for victim in victims {
+                let _ = fs::remove_file(directory.join(victim)).await;
+            }
+        });
+    }
+
+    // returns the filenames of evicted entries
+    fn finish_entry(
+        &self,
+        filepath: PathBuf,
+        encoding: String,
+        filename: String,
+        size: Option<u64>,
+    ) -> Vec<String> {
+        let mut victims = Vec::new();
+
+        let mut entries = match self.entries.lock() {
+            Ok(e) => e,
+            _ => return victims,
+        };
+
+        entries.pending.remove(&filename);
+
+        let size = match size {
+            Some(s) => s,
+            _ => return victims,
+        };
+
+        // the source changed, drop the outdated entry
+        if let Some(prev) = entries
+            .sources
+            .insert((filepath, encoding), filename.clone())
+        {
+            if prev != filename {
+                entries.remove(&prev);
+                victims.push(prev);
+            }
+        }
+
+        entries.tick += 1;
+        let tick = entries.tick;
+        if let Some((prev_size, _)) = entries.files.insert(filename, (size, tick)) {
+            entries.total_size -= prev_size;
+        }
+        entries.total_size += size;
+
+        victims.append(&mut entries.evict_oldest(self.max_size));
+
+        victims
+    }
+
+    fn remove_entry(&self, filename: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.remove(filename);
+        }
+    }
+}
+
+impl CacheEntries {
+    fn remove(&mut self, filename: &str) {
+        if let Some((size, _)) = self.files.remove(filename) {
+            self.total_size -= size;
+        }
+    }
+
+    // least recently used entries go first
+    fn evict_oldest(&mut self, max_size: u64) -> Vec<String> {
+        let mut victims = Vec::new();
+
+        while max_size < self.total_size {
+            let oldest = self
+                .files
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(name, _)| name.clone());
+
+            match oldest {
+                Some(name) => {
+                    self.remove(&name);
+                    victims.push(name);
+                }
+                _ => break,
+            }
+        }
+
+        victims
+    }
+}
+
+// runs once at startup, before any requests are served
+fn scan_cache_directory(
+    directory: &Path,
+    max_size: u64,
+    entries: &mut CacheEntries,
+) -> Vec<String> {
+    let mut victims = Vec::new();
+
+    let read_dir = match std::fs::read_dir(directory) {
+        Ok(rd) => rd,
+        _ => return victims,
+    };
+
+    let mut files = Vec::new();
+    for dir_entry in read_dir.flatten() {
+        let filename = dir_entry.file_name().to_string_lossy().to_string();
+        let metadata = match dir_entry.metadata() {
+            Ok(m) => m,
+            _ => continue,
+        };
+
+        // files the cache didn't write are left alone
+        if !metadata.is_file() {
+            continue;
+        }
+
+        // interrupted writes are never served
+        if is_cache_temp_filename(&filename) {
+            victims.push(filename);
+            continue;
+        }
+
+        if !is_cache_filename(&filename) {
+            continue;
+        }
+
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        files.push((modified, filename, metadata.len()));
+    }
+
+    // older files are evicted first
+    files.sort();
+    for (_, filename, size) in files {
+        entries.tick += 1;
+        entries.total_size += size;
+        entries.files.insert(filename, (size, entries.tick));
+    }
+
+    victims.append(&mut entries.evict_oldest(max_size));
+
+    victims
+}
+
+async fn write_cache_file(
+    directory: &Path,
+    file: fs::File,
+    filename: &str,
+    encoding: &str,
+) -> Result<u64, io::Error> {
+    fs::create_dir_all(directory).await?;
+
+    let mut encoder = match get_encoder(file, encoding) {
+        Some(e) => e,
+        _ => return Err(io::Error::other("unsupported encoding")),
+    };
+
+    // write then rename so partial files are never served
+    let count = TEMP_COUNT.fetch_add(1, Ordering::Relaxed);
+    let temp_path = directory.join(filename.to_string() + ".tmp" + &count.to_string());
+    let mut temp_file = fs::File::create(&temp_path).await?;
+
+    let written = match io::copy(&mut encoder, &mut temp_file).await {
+        Ok(w) => w,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(e);
+        }
+    };
+
+    temp_file.flush().await?;
+    fs::rename(&temp_path, directory.join(filename)).await?;
+
+    Ok(written)
+}
+
+// "0123456789abcdef.gz"
+fn is_cache_filename(filename: &str) -> bool {
+    let (hash, ext) = match (filename.get(..16), filename.get(16..)) {
+        (Some(h), Some(e)) => (h, e),
+        _ => return false,
+    };
+
+    hash.bytes()
+        .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        && ENCODINGS
+            .iter()
+            .any(|encoding| get_encoded_ext(encoding) == Some(ext))
+}
+
+// "0123456789abcdef.gz.tmp3"
+fn is_cache_temp_filename(filename: &str) -> bool {
+    match filename.rsplit_once(".tmp") {
+        Some((cache_filename, count)) => {
+            is_cache_filename(cache_filename)
+                && !count.is_empty()
+                && count.bytes().all(|b| b.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+// fnv-1a is stable across runs and rust versions
+fn get_cache_filename(filepath: &Path, metadata: &Metadata, encoding: &str) -> Option<String> {
+    let ext = get_encoded_ext(encoding)?;
+
+    let mtime = match metadata.modified().ok()?.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_nanos(),
+        _ => 0,
+    };
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let key = filepath.to_string_lossy().to_string()
+        + ":"
+        + &mtime.to_string()
+        + ":"
+        + &metadata.len().to_string()
+        + ":"
+        + encoding;
+
+    for byte in key.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    Some(format!("{:016x}{}", hash, ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // removed when dropped
+    struct TempDir {
+        path: PathBuf,
+    }
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!(
+                "file_server_compression_cache_{}_{}",
+                std::process::id(),
+                name
+            ));
+            std::fs::create_dir_all(&path).unwrap();
+
+            TempDir { path }
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn cache_filenames_match_the_naming_scheme() {
+        assert!(is_cache_filename("0123456789abcdef.gz"));
+        assert!(is_cache_filename("0123456789abcdef.zst"));
+        assert!(!is_cache_filename("0123456789ABCDEF.gz"));
+        assert!(!is_cache_filename("0123456789abcdef.txt"));
+        assert!(!is_cache_filename("0123456789abcde.gz"));
+        assert!(!is_cache_filename("backup.gz"));
+
+        assert!(is_cache_temp_filename("0123456789abcdef.br.tmp0"));
+        assert!(is_cache_temp_filename("0123456789abcdef.br.tmp12"));
+        assert!(!is_cache_temp_filename("0123456789abcdef.br.tmp"));
+        assert!(!is_cache_temp_filename("backup.tmp.sql"));
+        assert!(!is_cache_temp_filename("backup.tmp1"));
+    }
+
+    #[test]
+    fn scan_removes_only_cache_files() {
+        let temp = TempDir::new("scan");
+        let user_files = ["backup.tmp.sql", "notes.txt", "archive.gz"];
+        for filename in user_files {
+            std::fs::write(temp.path.join(filename), "user data").unwrap();
+        }
+        std::fs::write(temp.path.join("0123456789abcdef.gz.tmp3"), "partial").unwrap();
+        std::fs::write(temp.path.join("0123456789abcdef.gz"), "cached").unwrap();
+
+        // every cache file is over the max size
+        CompressionCache::from(Some(temp.path.clone()), Some(1));
+
+        for filename in user_files {
+            assert!(temp.path.join(filename).exists());
+        }
+        assert!(!temp.path.join("0123456789abcdef.gz.tmp3").exists());
+        assert!(!temp.path.join("0123456789abcdef.gz").exists());
+    }
+
+    #[test]
+    fn scan_adopts_cache_files_within_the_max_size() {
+        let temp = TempDir::new("adopt");
+        std::fs::write(temp.path.join("0123456789abcdef.gz"), "cached").unwrap();
+        std::fs::write(temp.path.join("notes.txt"), "user data").unwrap();
+
+        let mut entries = CacheEntries::default();
+        let victims = scan_cache_directory(&temp.path, 1024, &mut entries);
+
+        assert!(victims.is_empty());
+        assert_eq!(entries.files.len(), 1);
+        assert_eq!(entries.total_size, 6);
+    }
+}
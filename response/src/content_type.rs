@@ -1,10 +1,11 @@
-use std::path::PathBuf;
+use std::path::Path;
 
 pub const HTML: &str = "text/html; charset=utf-8";
+pub const JSON: &str = "application/json; charset=utf-8";
 pub const TEXT: &str = "text/plain; charset=utf-8";
 const OCTET: &str = "application/octet-stream";
 
-pub fn get_content_type(target_path: &PathBuf) -> &str {
+pub fn get_content_type(target_path: &Path) -> &str {
     let extension = match target_path.extension() {
         Some(ext) => ext,
         _ => return OCTET,
@@ -23,12 +24,13 @@ pub fn get_content_type(target_path: &PathBuf) -> &str {
         "flac" => "audio/flac",
         "gif" => "image/gif",
         "gz" => "application/gzip",
+        "htm" => HTML,
         "html" => HTML,
         "ico" => "image/vnd.microsoft.icon",
         "jpeg" => "image/jpeg",
         "jpg" => "image/jpeg",
         "js" => "text/javascript; charset=utf-8",
-        "json" => "application/json; charset=utf-8",
+        "json" => JSON,
         "webmanifest" => "application/manifest+json",
         "m3u8" => "application/x-mpegURL",
         "m3u" => "application/x-mpegURL",
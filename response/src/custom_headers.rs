@@ -0,0 +1,48 @@
+use config::HeaderRule;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::path_patterns::pattern_matches;
+
+#[derive(Clone, Debug)]
+pub struct HeaderRules {
+    rules: Vec<(String, HeaderMap)>,
+}
+
+impl HeaderRules {
+    pub fn from(potential_rules: Option<Vec<HeaderRule>>) -> HeaderRules {
+        let mut header_rules = HeaderRules { rules: Vec::new() };
+
+        if let Some(rules) = potential_rules {
+            for rule in rules {
+                let mut headers = HeaderMap::new();
+                for (name, value) in rule.headers {
+                    let header_name = match HeaderName::from_bytes(name.as_bytes()) {
+                        Ok(hn) => hn,
+                        _ => continue,
+                    };
+
+                    if let Ok(header_value) = HeaderValue::from_str(&value) {
+                        headers.insert(header_name, header_value);
+                    }
+                }
+
+                header_rules.rules.push((rule.pattern, headers));
+            }
+        }
+
+        header_rules
+    }
+
+    // every matching rule applies, later rules override earlier ones
+    pub fn get_headers(&self, url_path: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for (pattern, rule_headers) in &self.rules {
+            if pattern_matches(pattern, url_path) {
+                headers.extend(rule_headers.clone());
+            }
+        }
+
+        headers
+    }
+}
@@ -1,25 +1,43 @@
 use futures_util::TryStreamExt;
 use http_body_util::{BodyExt, StreamBody};
 use hyper::body::{Frame, Incoming};
-use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::header::{
+    HeaderMap, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED,
+};
 use hyper::http::{Request, Response};
 use hyper::StatusCode;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 use tokio_util::io::ReaderStream;
 
+use crate::autoindex;
+use crate::compression::build_compressed_body;
 use crate::content_type::get_content_type;
 use crate::last_resort_response;
 use crate::range_response;
-use crate::response_paths::{add_extension, get_encodings, get_path, get_path_from_request_url};
-use crate::type_flyweight::{BoxedResponse, ResponseParams, NOT_FOUND_404};
+use crate::redirect_response;
+use crate::response_headers::{add_headers, get_file_headers, get_vary_headers};
+use crate::response_paths::{
+    get_encoded_path, get_encodings, get_path, get_path_from_url_path, get_spa_path,
+    identity_is_acceptable, open_file,
+};
+use crate::type_flyweight::{BoxedResponse, ResponseParams, NOT_ACCEPTABLE_406, NOT_FOUND_404};
+use crate::validators::{
+    build_not_modified_response, get_etag, get_last_modified, is_not_modified,
+};
 
 pub async fn build_response(
     req: Request<Incoming>,
     res_params: ResponseParams,
+    url_path: &str,
 ) -> Result<BoxedResponse, hyper::http::Error> {
+    // redirect directories to a trailing slash
+    if let Some(res) = redirect_response::build_response(&req, &res_params, url_path).await {
+        return res;
+    }
+
     // check for range request
-    if let Some(res) = range_response::build_response(&req, &res_params).await {
+    if let Some(res) = range_response::build_response(&req, &res_params, url_path).await {
         return res;
     }
 
@@ -27,14 +45,22 @@ pub async fn build_response(
     let encodings = get_encodings(&req, &res_params.available_encodings);
 
     // serve file
-    if let Some(res) = build_req_path_response(&req, &res_params.directory, &encodings).await {
+    if let Some(res) = build_req_path_response(&req, &res_params, url_path, &encodings).await {
+        return res;
+    };
+
+    // list directory
+    if let Some(res) = autoindex::build_response(&req, &res_params, url_path).await {
+        return res;
+    };
+
+    // serve single page app
+    if let Some(res) = build_spa_response(&req, &res_params, url_path, &encodings).await {
         return res;
     };
 
     // serve 404
-    if let Some(res) =
-        build_not_found_response(&res_params.directory, &res_params.filepath_404, &encodings).await
-    {
+    if let Some(res) = build_not_found_response(&req, &res_params, &encodings).await {
         return res;
     };
 
@@ -43,59 +69,147 @@ pub async fn build_response(
 
 async fn build_req_path_response(
     req: &Request<Incoming>,
-    directory: &PathBuf,
+    res_params: &ResponseParams,
+    url_path: &str,
     encodings: &Option<Vec<String>>,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let filepath = match get_path_from_request_url(req, directory).await {
+    let filepath = match get_path_from_url_path(url_path, res_params).await {
         Some(fp) => fp,
         _ => return None,
     };
 
-    build_get_response(&filepath, StatusCode::OK, &encodings).await
+    let headers = get_file_headers(res_params, &filepath).await;
+
+    build_get_response(
+        req,
+        res_params,
+        &filepath,
+        StatusCode::OK,
+        encodings,
+        &headers,
+    )
+    .await
+}
+
+async fn build_spa_response(
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
+    url_path: &str,
+    encodings: &Option<Vec<String>>,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let filepath = get_spa_path(url_path, res_params).await?;
+    let headers = get_file_headers(res_params, &filepath).await;
+
+    build_get_response(
+        req,
+        res_params,
+        &filepath,
+        StatusCode::OK,
+        encodings,
+        &headers,
+    )
+    .await
 }
 
 async fn build_not_found_response(
-    directory: &PathBuf,
-    filepath_404: &Option<PathBuf>,
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
     encodings: &Option<Vec<String>>,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let fallback = match filepath_404 {
+    let fallback = match &res_params.filepath_404 {
         Some(fb) => fb,
         _ => return None,
     };
 
-    // file starts with directory
-    let filepath_404 = match get_path(directory, fallback).await {
+    // file starts with a mounted directory
+    let mount = res_params.mounts.get_file_mount(fallback)?;
+    let filepath_404 = match get_path(mount, fallback, res_params).await {
         Some(fb) => fb,
         _ => return None,
     };
 
-    build_get_response(&filepath_404, StatusCode::NOT_FOUND, &encodings).await
+    // 404s are never cached but can still be encoded
+    let headers = get_vary_headers(res_params, &filepath_404).await;
+
+    build_get_response(
+        req,
+        res_params,
+        &filepath_404,
+        StatusCode::NOT_FOUND,
+        encodings,
+        &headers,
+    )
+    .await
 }
 
 async fn build_get_response(
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
     filepath: &PathBuf,
     status_code: StatusCode,
     encodings: &Option<Vec<String>>,
+    headers: &HeaderMap,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let content_type = get_content_type(&filepath);
+    let content_type = get_content_type(filepath);
 
     // encodings
-    if let Some(res) =
-        compose_encoded_response(&filepath, content_type, status_code, &encodings).await
+    if let Some(res) = compose_encoded_response(
+        req,
+        res_params,
+        filepath,
+        content_type,
+        status_code,
+        encodings,
+        headers,
+    )
+    .await
+    {
+        return Some(res);
+    };
+
+    // encode on the fly
+    if let Some(res) = compose_compressed_response(
+        req,
+        res_params,
+        filepath,
+        content_type,
+        status_code,
+        encodings,
+        headers,
+    )
+    .await
     {
         return Some(res);
     };
 
     // origin target
-    compose_response(&filepath, content_type, status_code, None).await
+    if StatusCode::OK == status_code && !identity_is_acceptable(req) {
+        return Some(last_resort_response::build_response(
+            StatusCode::NOT_ACCEPTABLE,
+            NOT_ACCEPTABLE_406,
+        ));
+    }
+
+    compose_response(
+        req,
+        res_params,
+        filepath,
+        content_type,
+        status_code,
+        None,
+        headers,
+    )
+    .await
 }
 
 async fn compose_encoded_response(
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
     filepath: &PathBuf,
     content_type: &str,
     status_code: StatusCode,
     encodings: &Option<Vec<String>>,
+    headers: &HeaderMap,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
     let encds = match encodings {
         Some(encds) => encds,
@@ -103,9 +217,17 @@ async fn compose_encoded_response(
     };
 
     for enc in encds {
-        if let Some(encoded_path) = add_extension(filepath, &enc) {
-            if let Some(res) =
-                compose_response(&encoded_path, content_type, status_code, Some(enc)).await
+        if let Some(encoded_path) = get_encoded_path(res_params, filepath, enc).await {
+            if let Some(res) = compose_response(
+                req,
+                res_params,
+                &encoded_path,
+                content_type,
+                status_code,
+                Some(enc),
+                headers,
+            )
+            .await
             {
                 return Some(res);
             }
@@ -116,12 +238,16 @@ async fn compose_encoded_response(
 }
 
 async fn compose_response(
-    filepath: &PathBuf,
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
+    filepath: &Path,
     content_type: &str,
     status_code: StatusCode,
     content_encoding: Option<&str>,
+    headers: &HeaderMap,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let metadata = match fs::metadata(filepath).await {
+    let file = open_file(res_params, filepath).await?;
+    let metadata = match file.metadata().await {
         Ok(m) => m,
         _ => return None,
     };
@@ -130,12 +256,15 @@ async fn compose_response(
         return None;
     }
 
-    let file = match fs::File::open(filepath).await {
-        Ok(m) => m,
-        _ => return None,
-    };
+    let etag = get_etag(&metadata, content_encoding);
+    let last_modified = get_last_modified(&metadata);
+
+    // only the requested file is conditional, never the 404 fallback
+    if StatusCode::OK == status_code && is_not_modified(req, &etag, &metadata) {
+        return Some(build_not_modified_response(&etag, &last_modified, headers));
+    }
 
-    let mut builder = Response::builder()
+    let mut builder = add_headers(Response::builder(), headers)
         .status(status_code)
         .header(CONTENT_TYPE, content_type)
         .header(CONTENT_LENGTH, metadata.len());
@@ -144,6 +273,13 @@ async fn compose_response(
         builder = builder.header(CONTENT_ENCODING, enc);
     }
 
+    if StatusCode::OK == status_code {
+        builder = builder.header(ETAG, etag);
+        if let Some(lm) = last_modified {
+            builder = builder.header(LAST_MODIFIED, lm);
+        }
+    }
+
     // https://github.com/hyperium/hyper/blob/master/examples/send_file.rs
     let reader_stream = ReaderStream::new(file);
     let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
@@ -151,3 +287,88 @@ async fn compose_response(
 
     Some(builder.body(boxed_body))
 }
+
+async fn compose_compressed_response(
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
+    filepath: &PathBuf,
+    content_type: &str,
+    status_code: StatusCode,
+    encodings: &Option<Vec<String>>,
+    headers: &HeaderMap,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let compression = &res_params.compression;
+
+    let metadata = match fs::metadata(filepath).await {
+        Ok(m) => m,
+        _ => return None,
+    };
+
+    if !metadata.is_file() {
+        return None;
+    }
+
+    let encoding = compression.get_encoding(content_type, metadata.len(), encodings)?;
+
+    // validators come from the file that is served
+    let file = open_file(res_params, filepath).await?;
+    let metadata = match file.metadata().await {
+        Ok(m) => m,
+        _ => return None,
+    };
+
+    // compressed output is not byte-for-byte stable, so the etag is weak
+    // cached copies share the source file's validators
+    let etag = "W/".to_string() + &get_etag(&metadata, Some(encoding));
+    let last_modified = get_last_modified(&metadata);
+
+    if StatusCode::OK == status_code && is_not_modified(req, &etag, &metadata) {
+        return Some(build_not_modified_response(&etag, &last_modified, headers));
+    }
+
+    let mut builder = add_headers(Response::builder(), headers)
+        .status(status_code)
+        .header(CONTENT_TYPE, content_type)
+        .header(CONTENT_ENCODING, encoding);
+
+    if StatusCode::OK == status_code {
+        builder = builder.header(ETAG, etag);
+        if let Some(lm) = last_modified {
+            builder = builder.header(LAST_MODIFIED, lm);
+        }
+    }
+
+    // previously compressed files are served like precompressed siblings
+    if let Some(cached_path) = compression
+        .cache
+        .get_cached_path(filepath, &metadata, encoding)
+        .await
+    {
+        if let Ok(cached_file) = fs::File::open(&cached_path).await {
+            if let Ok(cached_metadata) = cached_file.metadata().await {
+                let reader_stream = ReaderStream::new(cached_file);
+                let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
+
+                return Some(
+                    builder
+                        .header(CONTENT_LENGTH, cached_metadata.len())
+                        .body(stream_body.boxed()),
+                );
+            }
+        }
+    }
+
+    let boxed_body = build_compressed_body(file, encoding)?;
+
+    // the cache reads its own copy of the file
+    if compression.cache.is_enabled() {
+        if let Some(cache_file) = open_file(res_params, filepath).await {
+            compression
+                .cache
+                .insert(filepath, &metadata, encoding, cache_file);
+        }
+    }
+
+    // no content-length, the body is sent chunked or in http2 frames
+    Some(builder.body(boxed_body))
+}
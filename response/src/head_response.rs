@@ -1,32 +1,59 @@
 use http_body_util::{BodyExt, Full};
 use hyper::body::Incoming;
-use hyper::header::{ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::header::{
+    HeaderMap, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED,
+};
 use hyper::http::{Request, Response};
 use hyper::StatusCode;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 
+use crate::autoindex;
 use crate::content_type::get_content_type;
 use crate::last_resort_response;
-use crate::response_paths::{add_extension, get_encodings, get_path_from_request_url};
-use crate::type_flyweight::{BoxedResponse, ResponseParams, NOT_FOUND_404};
+use crate::redirect_response;
+use crate::response_headers::{add_headers, get_file_headers};
+use crate::response_paths::{
+    get_encoded_path, get_encodings, get_path_from_url_path, get_spa_path, identity_is_acceptable,
+    open_file,
+};
+use crate::type_flyweight::{BoxedResponse, ResponseParams, NOT_ACCEPTABLE_406, NOT_FOUND_404};
+use crate::validators::{
+    build_not_modified_response, get_etag, get_last_modified, is_not_modified,
+};
 
 pub async fn build_response(
     req: Request<Incoming>,
     res_params: ResponseParams,
+    url_path: &str,
 ) -> Result<BoxedResponse, hyper::http::Error> {
-    let encodings = get_encodings(&req, &res_params.available_encodings);
+    // redirect directories to a trailing slash
+    if let Some(res) = redirect_response::build_response(&req, &res_params, url_path).await {
+        return res;
+    }
 
-    if let Some(filepath) = get_path_from_request_url(&req, &res_params.directory).await {
-        let content_type = get_content_type(&filepath);
+    let encodings = get_encodings(&req, &res_params.available_encodings);
 
-        // encodings
-        if let Some(res) = compose_encoded_response(&filepath, content_type, encodings).await {
+    if let Some(filepath) = get_path_from_url_path(url_path, &res_params).await {
+        if let Some(res) = build_head_response(&req, &res_params, &filepath, &encodings).await {
             return res;
-        };
+        }
+    };
+
+    // list directory without a body
+    if let Some(res) = autoindex::build_response(&req, &res_params, url_path).await {
+        return res.map(|response| {
+            response.map(|_| {
+                Full::new(bytes::Bytes::new())
+                    .map_err(|e| match e {})
+                    .boxed()
+            })
+        });
+    };
 
-        // origin target
-        if let Some(res) = compose_response(&filepath, content_type, None).await {
+    // serve single page app
+    if let Some(filepath) = get_spa_path(url_path, &res_params).await {
+        if let Some(res) = build_head_response(&req, &res_params, &filepath, &encodings).await {
             return res;
         }
     };
@@ -34,10 +61,48 @@ pub async fn build_response(
     last_resort_response::build_response(StatusCode::NOT_FOUND, NOT_FOUND_404)
 }
 
+async fn build_head_response(
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
+    filepath: &PathBuf,
+    encodings: &Option<Vec<String>>,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let content_type = get_content_type(filepath);
+    let headers = get_file_headers(res_params, filepath).await;
+
+    // encodings
+    if let Some(res) =
+        compose_encoded_response(req, res_params, filepath, content_type, encodings, &headers).await
+    {
+        return Some(res);
+    };
+
+    // encode on the fly
+    if let Some(res) =
+        compose_compressed_response(req, res_params, filepath, content_type, encodings, &headers)
+            .await
+    {
+        return Some(res);
+    };
+
+    // origin target
+    if !identity_is_acceptable(req) {
+        return Some(last_resort_response::build_response(
+            StatusCode::NOT_ACCEPTABLE,
+            NOT_ACCEPTABLE_406,
+        ));
+    }
+
+    compose_response(req, res_params, filepath, content_type, None, &headers).await
+}
+
 async fn compose_encoded_response(
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
     filepath: &PathBuf,
     content_type: &str,
-    content_encodings: Option<Vec<String>>,
+    content_encodings: &Option<Vec<String>>,
+    headers: &HeaderMap,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
     let encodings = match content_encodings {
         Some(encds) => encds,
@@ -45,9 +110,16 @@ async fn compose_encoded_response(
     };
 
     for content_encoding in encodings {
-        if let Some(encoded_path) = add_extension(filepath, &content_encoding) {
-            if let Some(res) =
-                compose_response(&encoded_path, content_type, Some(content_encoding)).await
+        if let Some(encoded_path) = get_encoded_path(res_params, filepath, content_encoding).await {
+            if let Some(res) = compose_response(
+                req,
+                res_params,
+                &encoded_path,
+                content_type,
+                Some(content_encoding.to_string()),
+                headers,
+            )
+            .await
             {
                 return Some(res);
             }
@@ -58,11 +130,15 @@ async fn compose_encoded_response(
 }
 
 async fn compose_response(
-    filepath: &PathBuf,
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
+    filepath: &Path,
     content_type: &str,
     content_encoding: Option<String>,
+    headers: &HeaderMap,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let metadata = match fs::metadata(filepath).await {
+    let file = open_file(res_params, filepath).await?;
+    let metadata = match file.metadata().await {
         Ok(m) => m,
         _ => return None,
     };
@@ -71,11 +147,23 @@ async fn compose_response(
         return None;
     }
 
-    let mut builder = Response::builder()
+    let etag = get_etag(&metadata, content_encoding.as_deref());
+    let last_modified = get_last_modified(&metadata);
+
+    if is_not_modified(req, &etag, &metadata) {
+        return Some(build_not_modified_response(&etag, &last_modified, headers));
+    }
+
+    let mut builder = add_headers(Response::builder(), headers)
         .status(StatusCode::OK)
         .header(CONTENT_TYPE, content_type)
         .header(ACCEPT_RANGES, "bytes")
-        .header(CONTENT_LENGTH, metadata.len());
+        .header(CONTENT_LENGTH, metadata.len())
+        .header(ETAG, etag);
+
+    if let Some(lm) = last_modified {
+        builder = builder.header(LAST_MODIFIED, lm);
+    }
 
     if let Some(enc) = content_encoding {
         builder = builder.header(CONTENT_ENCODING, enc);
@@ -89,3 +177,69 @@ async fn compose_response(
         ),
     )
 }
+
+async fn compose_compressed_response(
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
+    filepath: &PathBuf,
+    content_type: &str,
+    encodings: &Option<Vec<String>>,
+    headers: &HeaderMap,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let compression = &res_params.compression;
+
+    let metadata = match fs::metadata(filepath).await {
+        Ok(m) => m,
+        _ => return None,
+    };
+
+    if !metadata.is_file() {
+        return None;
+    }
+
+    let encoding = compression.get_encoding(content_type, metadata.len(), encodings)?;
+
+    // validators come from the file that would be served
+    let file = open_file(res_params, filepath).await?;
+    let metadata = match file.metadata().await {
+        Ok(m) => m,
+        _ => return None,
+    };
+
+    // cached copies share the source file's validators
+    let etag = "W/".to_string() + &get_etag(&metadata, Some(encoding));
+    let last_modified = get_last_modified(&metadata);
+
+    if is_not_modified(req, &etag, &metadata) {
+        return Some(build_not_modified_response(&etag, &last_modified, headers));
+    }
+
+    let mut builder = add_headers(Response::builder(), headers)
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type)
+        .header(CONTENT_ENCODING, encoding)
+        .header(ETAG, etag);
+
+    if let Some(lm) = last_modified {
+        builder = builder.header(LAST_MODIFIED, lm);
+    }
+
+    // only previously compressed files have a known length
+    if let Some(cached_path) = compression
+        .cache
+        .get_cached_path(filepath, &metadata, encoding)
+        .await
+    {
+        if let Ok(cached_metadata) = fs::metadata(&cached_path).await {
+            builder = builder.header(CONTENT_LENGTH, cached_metadata.len());
+        }
+    }
+
+    Some(
+        builder.body(
+            Full::new(bytes::Bytes::new())
+                .map_err(|e| match e {})
+                .boxed(),
+        ),
+    )
+}
@@ -1,12 +1,25 @@
+mod autoindex;
 mod available_encodings;
+mod cache_control;
+mod compression;
+mod compression_cache;
 mod content_type;
+mod custom_headers;
 mod get_response;
 mod head_response;
 mod last_resort_response;
+mod mounts;
+mod path_patterns;
+mod path_policy;
 mod range_response;
+mod redirect_response;
+mod response_headers;
 mod response_paths;
 mod responses;
+mod spa_fallback;
 mod type_flyweight;
+mod url_paths;
+mod validators;
 
 pub use crate::responses::build_response;
 pub use crate::type_flyweight::{BoxedResponse, ResponseParams};
@@ -0,0 +1,86 @@
+use config::MountConfig;
+use std::path::{Path, PathBuf};
+
+use crate::response_paths::get_url_path;
+
+// url path prefixes mapped to directories
+// the configured directory is mounted at "/"
+#[derive(Clone, Debug)]
+pub struct Mounts {
+    mounts: Vec<Mount>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Mount {
+    prefix: String,
+    pub directory: PathBuf,
+    // resolved once, symlinks are compared against it
+    pub canonical_directory: PathBuf,
+}
+
+impl Mounts {
+    pub fn from(directory: PathBuf, potential_mounts: Option<Vec<MountConfig>>) -> Mounts {
+        let mut mounts = Vec::new();
+
+        if let Some(mount_configs) = potential_mounts {
+            for mount in mount_configs {
+                let prefix = mount.prefix.trim_end_matches("/").to_string();
+                mounts.push(Mount::from(prefix, mount.directory));
+            }
+        }
+
+        mounts.push(Mount::from("".to_string(), directory));
+
+        // longest prefix first, a configured "/" mount replaces the directory
+        mounts.sort_by_key(|mount| std::cmp::Reverse(mount.prefix.len()));
+
+        Mounts { mounts }
+    }
+
+    // prefixes match whole path segments: "/docs" matches "/docs/a" but not "/docsa"
+    pub fn get_mount<'a>(&self, url_path: &'a str) -> Option<(&Mount, &'a str)> {
+        for mount in &self.mounts {
+            if let Some(mounted_path) = url_path.strip_prefix(mount.prefix.as_str()) {
+                if mounted_path.is_empty() || mounted_path.starts_with("/") {
+                    return Some((mount, mounted_path));
+                }
+            }
+        }
+
+        None
+    }
+
+    // the mount a filepath resides in
+    pub fn get_file_mount(&self, filepath: &Path) -> Option<&Mount> {
+        self.mounts
+            .iter()
+            .find(|mount| filepath.starts_with(&mount.directory))
+    }
+
+    // filepath as a url path including its mount prefix: "/docs/index.html"
+    pub fn get_url_path(&self, filepath: &Path) -> String {
+        for mount in &self.mounts {
+            if filepath.starts_with(&mount.directory) {
+                return mount.prefix.to_string() + &get_url_path(&mount.directory, filepath);
+            }
+        }
+
+        get_url_path(Path::new(""), filepath)
+    }
+}
+
+impl Mount {
+    // a directory missing at startup keeps its configured path
+    fn from(prefix: String, directory: PathBuf) -> Mount {
+        let canonical_directory = match std::fs::canonicalize(&directory) {
+            Ok(pb) => pb,
+            _ => directory.clone(),
+        };
+
+        Mount {
+            prefix,
+            directory,
+            canonical_directory,
+        }
+    }
+}
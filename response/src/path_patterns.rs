@@ -0,0 +1,51 @@
+// patterns without a "/" match against a file name: "*.js"
+// patterns with a "/" match against an entire url path: "/assets/*"
+// "*" matches any run of characters, "?" matches a single character
+pub fn pattern_matches(pattern: &str, url_path: &str) -> bool {
+    let target = match pattern.contains("/") {
+        true => url_path,
+        _ => match url_path.rsplit_once("/") {
+            Some((_, file_name)) => file_name,
+            _ => url_path,
+        },
+    };
+
+    let pattern_chars: Vec<char> = pattern.chars().collect();
+    let target_chars: Vec<char> = target.chars().collect();
+
+    glob_matches(&pattern_chars, &target_chars)
+}
+
+// backtracks to the most recent "*" on a mismatch
+fn glob_matches(pattern: &[char], target: &[char]) -> bool {
+    let mut p_index = 0;
+    let mut t_index = 0;
+    let mut star: Option<(usize, usize)> = None;
+
+    while t_index < target.len() {
+        if let Some(p) = pattern.get(p_index) {
+            if '*' == *p {
+                star = Some((p_index, t_index));
+                p_index += 1;
+                continue;
+            }
+
+            if '?' == *p || target[t_index] == *p {
+                p_index += 1;
+                t_index += 1;
+                continue;
+            }
+        }
+
+        match star {
+            Some((star_p, star_t)) => {
+                p_index = star_p + 1;
+                t_index = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            _ => return false,
+        }
+    }
+
+    pattern[p_index..].iter().all(|p| '*' == *p)
+}
@@ -0,0 +1,394 @@
+use config::{Config, DotfilePolicy, SymlinkPolicy};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::mounts::Mount;
+use crate::path_patterns::pattern_matches;
+
+// https://www.rfc-editor.org/rfc/rfc8615
+const WELL_KNOWN: &str = ".well-known";
+
+// decides which files inside a served directory can be served
+#[derive(Clone, Debug)]
+pub struct PathPolicy {
+    symlinks: SymlinkPolicy,
+    dotfiles: DotfilePolicy,
+    deny_patterns: Vec<String>,
+}
+
+impl PathPolicy {
+    pub fn from(config: &Config) -> PathPolicy {
+        PathPolicy {
+            symlinks: config
+                .symlinks
+                .clone()
+                .unwrap_or(SymlinkPolicy::FollowWithinRoot),
+            dotfiles: config.dotfiles.clone().unwrap_or(DotfilePolicy::Ignore),
+            deny_patterns: config.deny_patterns.clone().unwrap_or_default(),
+        }
+    }
+
+    // requests for these paths receive a 403
+    pub fn is_forbidden(&self, url_path: &str) -> bool {
+        if let DotfilePolicy::Deny = self.dotfiles {
+            if url_path.split("/").any(is_dotfile) {
+                return true;
+            }
+        }
+
+        self.is_denied(url_path)
+    }
+
+    // patterns without a "/" match any segment, so ".git" denies "/.git/config"
+    pub fn is_denied(&self, url_path: &str) -> bool {
+        self.deny_patterns
+            .iter()
+            .any(|pattern| match pattern.contains("/") {
+                true => pattern_matches(pattern, url_path),
+                _ => url_path
+                    .split("/")
+                    .any(|segment| pattern_matches(pattern, segment)),
+            })
+    }
+
+    // filepath must lexically reside in the mount's directory
+    //
+    // a check before a file is opened, the opened file is checked again
+    pub async fn allows(&self, mount: &Mount, filepath: &Path) -> bool {
+        if !self.allows_dotfiles(&mount.directory, filepath) {
+            return false;
+        }
+
+        match self.symlinks {
+            SymlinkPolicy::Follow => true,
+            SymlinkPolicy::FollowWithinRoot => resolves_within(mount, filepath).await,
+            SymlinkPolicy::Deny => !has_symlinks(&mount.directory, filepath).await,
+        }
+    }
+
+    // the opened file is checked rather than its path, so a symlink
+    // swapped in after `allows` is never served
+    pub async fn open(&self, mount: &Mount, filepath: &Path) -> Option<fs::File> {
+        if !self.allows_dotfiles(&mount.directory, filepath) {
+            return None;
+        }
+
+        let file = match fs::File::open(filepath).await {
+            Ok(f) => f,
+            _ => return None,
+        };
+
+        if let SymlinkPolicy::Follow = self.symlinks {
+            return Some(file);
+        }
+
+        let opened_path = get_opened_path(&file, filepath).await?;
+        let allowed = match self.symlinks {
+            SymlinkPolicy::Deny => {
+                // without symlinks the real path mirrors the lexical one
+                let relative = filepath.strip_prefix(&mount.directory).ok()?;
+                opened_path == mount.canonical_directory.join(relative)
+            }
+            _ => opened_path.starts_with(&mount.canonical_directory),
+        };
+
+        match allowed {
+            true => Some(file),
+            _ => None,
+        }
+    }
+
+    // only names beneath the directory count
+    fn allows_dotfiles(&self, directory: &Path, filepath: &Path) -> bool {
+        if let DotfilePolicy::Allow = self.dotfiles {
+            return true;
+        }
+
+        let relative = match filepath.strip_prefix(directory) {
+            Ok(r) => r,
+            _ => return false,
+        };
+
+        !relative
+            .components()
+            .any(|component| is_dotfile(&component.as_os_str().to_string_lossy()))
+    }
+}
+
+fn is_dotfile(name: &str) -> bool {
+    name.starts_with(".") && WELL_KNOWN != name
+}
+
+// symlinks are resolved before comparing, so "../" targets
+// and links to absolute paths can't escape
+async fn resolves_within(mount: &Mount, filepath: &Path) -> bool {
+    match fs::canonicalize(filepath).await {
+        Ok(pb) => pb.starts_with(&mount.canonical_directory),
+        _ => false,
+    }
+}
+
+// the directory itself may be a symlink, nothing beneath it can be
+// missing files count as symlinks, there is nothing to serve
+async fn has_symlinks(directory: &Path, filepath: &Path) -> bool {
+    let relative = match filepath.strip_prefix(directory) {
+        Ok(r) => r,
+        _ => return true,
+    };
+
+    let mut path = directory.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+
+        match fs::symlink_metadata(&path).await {
+            Ok(m) if !m.file_type().is_symlink() => {}
+            _ => return true,
+        }
+    }
+
+    false
+}
+
+// the real path of an opened file, later changes to its path can't affect it
+#[cfg(target_os = "linux")]
+async fn get_opened_path(file: &fs::File, filepath: &Path) -> Option<PathBuf> {
+    use std::os::fd::AsRawFd;
+
+    let fd_path = "/proc/self/fd/".to_string() + &file.as_raw_fd().to_string();
+    match fs::read_link(fd_path).await {
+        Ok(pb) => Some(pb),
+        _ => get_canonical_path(file, filepath).await,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn get_opened_path(file: &fs::File, filepath: &Path) -> Option<PathBuf> {
+    get_canonical_path(file, filepath).await
+}
+
+// the canonical path must still lead to the opened file
+async fn get_canonical_path(file: &fs::File, filepath: &Path) -> Option<PathBuf> {
+    let canonical_path = match fs::canonicalize(filepath).await {
+        Ok(pb) => pb,
+        _ => return None,
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let opened = file.metadata().await.ok()?;
+        let current = fs::metadata(&canonical_path).await.ok()?;
+        if opened.dev() != current.dev() || opened.ino() != current.ino() {
+            return None;
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = file;
+
+    Some(canonical_path)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::mounts::Mounts;
+    use std::os::unix::fs::symlink;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static TEMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    // removed when dropped
+    struct TempDir {
+        path: PathBuf,
+    }
+
+    impl TempDir {
+        fn new() -> TempDir {
+            let count = TEMP_COUNT.fetch_add(1, Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!(
+                "file_server_path_policy_{}_{}",
+                std::process::id(),
+                count
+            ));
+            std::fs::create_dir_all(path.join("root")).unwrap();
+            std::fs::create_dir_all(path.join("outside")).unwrap();
+            std::fs::write(path.join("outside/secret.txt"), "secret").unwrap();
+
+            TempDir { path }
+        }
+
+        fn root(&self) -> PathBuf {
+            self.path.join("root")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    fn get_policy(symlinks: SymlinkPolicy) -> PathPolicy {
+        PathPolicy {
+            symlinks,
+            dotfiles: DotfilePolicy::Allow,
+            deny_patterns: Vec::new(),
+        }
+    }
+
+    fn get_mounts(directory: PathBuf) -> Mounts {
+        Mounts::from(directory, None)
+    }
+
+    #[test]
+    fn deny_patterns_without_a_slash_match_every_segment() {
+        let policy = PathPolicy {
+            symlinks: SymlinkPolicy::FollowWithinRoot,
+            dotfiles: DotfilePolicy::Allow,
+            deny_patterns: vec![".git".to_string(), "*.bak".to_string()],
+        };
+
+        assert!(policy.is_denied("/.git"));
+        assert!(policy.is_denied("/.git/config"));
+        assert!(policy.is_denied("/vendor/.git/HEAD"));
+        assert!(policy.is_denied("/backups/a.bak"));
+        assert!(!policy.is_denied("/.github/workflows/ci.yml"));
+        assert!(!policy.is_denied("/a.bak.txt"));
+    }
+
+    #[test]
+    fn deny_patterns_with_a_slash_match_the_url_path() {
+        let policy = PathPolicy {
+            symlinks: SymlinkPolicy::FollowWithinRoot,
+            dotfiles: DotfilePolicy::Allow,
+            deny_patterns: vec!["/drafts/*".to_string()],
+        };
+
+        assert!(policy.is_denied("/drafts/post.html"));
+        assert!(!policy.is_denied("/blog/drafts/post.html"));
+    }
+
+    #[tokio::test]
+    async fn link_to_etc_is_not_served() {
+        let temp = TempDir::new();
+        symlink("/etc", temp.root().join("etc")).unwrap();
+
+        let mounts = get_mounts(temp.root());
+        let (mount, _) = mounts.get_mount("/").unwrap();
+        let filepath = temp.root().join("etc/passwd");
+
+        let policy = get_policy(SymlinkPolicy::FollowWithinRoot);
+        assert!(!policy.allows(mount, &filepath).await);
+        assert!(policy.open(mount, &filepath).await.is_none());
+
+        let policy = get_policy(SymlinkPolicy::Deny);
+        assert!(!policy.allows(mount, &filepath).await);
+        assert!(policy.open(mount, &filepath).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn parent_directory_link_is_not_served() {
+        let temp = TempDir::new();
+        symlink("..", temp.root().join("up")).unwrap();
+
+        let mounts = get_mounts(temp.root());
+        let (mount, _) = mounts.get_mount("/").unwrap();
+        let filepath = temp.root().join("up/outside/secret.txt");
+
+        let policy = get_policy(SymlinkPolicy::FollowWithinRoot);
+        assert!(!policy.allows(mount, &filepath).await);
+        assert!(policy.open(mount, &filepath).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn links_within_root_are_followed() {
+        let temp = TempDir::new();
+        std::fs::create_dir(temp.root().join("real")).unwrap();
+        std::fs::write(temp.root().join("real/a.txt"), "a").unwrap();
+        symlink("real", temp.root().join("linked")).unwrap();
+
+        let mounts = get_mounts(temp.root());
+        let (mount, _) = mounts.get_mount("/").unwrap();
+        let filepath = temp.root().join("linked/a.txt");
+
+        let policy = get_policy(SymlinkPolicy::FollowWithinRoot);
+        assert!(policy.allows(mount, &filepath).await);
+        assert!(policy.open(mount, &filepath).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn deny_rejects_symlinked_parent_directories() {
+        let temp = TempDir::new();
+        std::fs::create_dir(temp.root().join("real")).unwrap();
+        std::fs::write(temp.root().join("real/a.txt"), "a").unwrap();
+        symlink("real", temp.root().join("linked")).unwrap();
+
+        let mounts = get_mounts(temp.root());
+        let (mount, _) = mounts.get_mount("/").unwrap();
+        let policy = get_policy(SymlinkPolicy::Deny);
+
+        let linked_path = temp.root().join("linked/a.txt");
+        assert!(!policy.allows(mount, &linked_path).await);
+        assert!(policy.open(mount, &linked_path).await.is_none());
+
+        let real_path = temp.root().join("real/a.txt");
+        assert!(policy.allows(mount, &real_path).await);
+        assert!(policy.open(mount, &real_path).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn deny_allows_a_symlinked_root() {
+        let temp = TempDir::new();
+        std::fs::write(temp.root().join("a.txt"), "a").unwrap();
+        symlink(temp.root(), temp.path.join("root_link")).unwrap();
+
+        let mounts = get_mounts(temp.path.join("root_link"));
+        let (mount, _) = mounts.get_mount("/").unwrap();
+        let filepath = temp.path.join("root_link/a.txt");
+
+        let policy = get_policy(SymlinkPolicy::Deny);
+        assert!(policy.allows(mount, &filepath).await);
+        assert!(policy.open(mount, &filepath).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn file_swapped_for_a_link_after_the_check_is_not_served() {
+        for symlinks in [SymlinkPolicy::FollowWithinRoot, SymlinkPolicy::Deny] {
+            let temp = TempDir::new();
+            let filepath = temp.root().join("a.txt");
+            std::fs::write(&filepath, "a").unwrap();
+
+            let mounts = get_mounts(temp.root());
+            let (mount, _) = mounts.get_mount("/").unwrap();
+            let policy = get_policy(symlinks);
+            assert!(policy.allows(mount, &filepath).await);
+
+            std::fs::remove_file(&filepath).unwrap();
+            symlink(temp.path.join("outside/secret.txt"), &filepath).unwrap();
+
+            assert!(policy.open(mount, &filepath).await.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn directory_swapped_for_a_link_after_the_check_is_not_served() {
+        for symlinks in [SymlinkPolicy::FollowWithinRoot, SymlinkPolicy::Deny] {
+            let temp = TempDir::new();
+            std::fs::create_dir(temp.root().join("sub")).unwrap();
+            std::fs::write(temp.root().join("sub/secret.txt"), "a").unwrap();
+            let filepath = temp.root().join("sub/secret.txt");
+
+            let mounts = get_mounts(temp.root());
+            let (mount, _) = mounts.get_mount("/").unwrap();
+            let policy = get_policy(symlinks);
+            assert!(policy.allows(mount, &filepath).await);
+
+            std::fs::rename(temp.root().join("sub"), temp.root().join("old")).unwrap();
+            symlink(temp.path.join("outside"), temp.root().join("sub")).unwrap();
+
+            assert!(policy.open(mount, &filepath).await.is_none());
+        }
+    }
+}
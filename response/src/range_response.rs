@@ -1,32 +1,53 @@
-use futures_util::TryStreamExt;
+use bytes::Bytes;
+use futures_util::{future, stream, StreamExt, TryStreamExt};
 use http_body_util::{BodyExt, StreamBody};
 use hyper::body::Frame;
 use hyper::body::Incoming as IncomingBody;
-use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use hyper::header::{
+    HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+    LAST_MODIFIED, RANGE,
+};
+use hyper::http::response::Builder;
 use hyper::http::{Request, Response, StatusCode};
 use std::io::SeekFrom;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
 use tokio::fs::File;
-use tokio::io::AsyncSeekExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, Take};
 use tokio_util::io::ReaderStream;
 
 use crate::content_type::get_content_type;
 use crate::last_resort_response;
-use crate::response_paths::{add_extension, get_encodings, get_path_from_request_url};
+use crate::response_headers::{add_headers, get_file_headers};
+use crate::response_paths::{
+    get_encoded_path, get_encodings, get_path_from_url_path, identity_is_acceptable, open_file,
+};
 use crate::type_flyweight::{
-    BoxedResponse, ResponseParams, BAD_REQUEST_400, NOT_FOUND_404, RANGE_NOT_SATISFIABLE_416,
+    BoxedResponse, ResponseParams, NOT_ACCEPTABLE_406, RANGE_NOT_SATISFIABLE_416,
+};
+use crate::validators::{
+    build_not_modified_response, get_etag, get_last_modified, if_range_matches, is_not_modified,
 };
 
 // Range: <unit>=<range-start>-
 // Range: <unit>=<range-start>-<range-end>
 // Range: <unit>=-<suffix-length>
-
-// multi range requests require an entirely different strategy
 // Range: <unit>=<range-start>-<range-end>, …, <range-startN>-<range-endN>
 
+// multipart responses beyond this many (coalesced) ranges are ignored
+// and the full representation is served instead
+const MAX_RANGES: usize = 32;
+
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+static BOUNDARY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
 pub async fn build_response(
     req: &Request<IncomingBody>,
     res_params: &ResponseParams,
+    url_path: &str,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
     // bail if no range header
     let range_header = match get_range_header(req) {
@@ -34,25 +55,26 @@ pub async fn build_response(
         _ => return None,
     };
 
-    if let Some(filepath) = get_path_from_request_url(req, &res_params.directory).await {
+    if let Some(filepath) = get_path_from_url_path(url_path, res_params).await {
         if let Some(ranges) = get_ranges(&range_header) {
             let encodings = get_encodings(req, &res_params.available_encodings);
 
-            if let Some(res) = build_single_range_response(&filepath, encodings, ranges).await {
-                return Some(res);
-            }
+            let headers = get_file_headers(res_params, &filepath).await;
+
+            return build_range_response(req, res_params, &filepath, encodings, ranges, &headers)
+                .await;
         };
 
-        return Some(last_resort_response::build_response(
-            StatusCode::RANGE_NOT_SATISFIABLE,
-            RANGE_NOT_SATISFIABLE_416,
-        ));
+        let size = match fs::metadata(&filepath).await {
+            Ok(metadata) => metadata.len() as usize,
+            _ => return None,
+        };
+
+        return Some(build_range_not_satisfiable_response(size));
     }
 
-    Some(last_resort_response::build_response(
-        StatusCode::NOT_FOUND,
-        NOT_FOUND_404,
-    ))
+    // directory listings and 404s ignore ranges
+    None
 }
 
 fn get_range_header(req: &Request<IncomingBody>) -> Option<String> {
@@ -109,7 +131,7 @@ fn get_ranges(range_header_value: &str) -> Option<Vec<(Option<usize>, Option<usi
         ranges.push(start_end_range)
     }
 
-    if 0 < ranges.len() {
+    if !ranges.is_empty() {
         return Some(ranges);
     }
 
@@ -139,39 +161,65 @@ fn get_window_range(range_chunk: &str) -> Option<(Option<usize>, Option<usize>)>
         _ => return None,
     };
 
-    if start_range_int < end_range_int {
+    if start_range_int <= end_range_int {
         return Some((Some(start_range_int), Some(end_range_int)));
     }
 
     None
 }
 
-async fn build_single_range_response(
+async fn build_range_response(
+    req: &Request<IncomingBody>,
+    res_params: &ResponseParams,
     filepath: &PathBuf,
     encodings: Option<Vec<String>>,
     ranges: Vec<(Option<usize>, Option<usize>)>,
+    headers: &HeaderMap,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    if 1 != ranges.len() {
-        return None;
-    };
-
-    let content_type = get_content_type(&filepath);
-
-    if let Some(res) =
-        compose_encoded_single_range_response(&filepath, content_type, &encodings, &ranges).await
+    let content_type = get_content_type(filepath);
+
+    if let Some(res) = compose_encoded_range_response(
+        req,
+        res_params,
+        filepath,
+        content_type,
+        &encodings,
+        &ranges,
+        headers,
+    )
+    .await
     {
         return Some(res);
     };
 
     // origin target
-    compose_single_range_response(&filepath, content_type, None, &ranges).await
+    if !identity_is_acceptable(req) {
+        return Some(last_resort_response::build_response(
+            StatusCode::NOT_ACCEPTABLE,
+            NOT_ACCEPTABLE_406,
+        ));
+    }
+
+    compose_range_response(
+        req,
+        res_params,
+        filepath,
+        content_type,
+        None,
+        &ranges,
+        headers,
+    )
+    .await
 }
 
-async fn compose_encoded_single_range_response(
+async fn compose_encoded_range_response(
+    req: &Request<IncomingBody>,
+    res_params: &ResponseParams,
     filepath: &PathBuf,
     content_type: &str,
     encodings: &Option<Vec<String>>,
-    ranges: &Vec<(Option<usize>, Option<usize>)>,
+    ranges: &[(Option<usize>, Option<usize>)],
+    headers: &HeaderMap,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
     let encds = match encodings {
         Some(encds) => encds,
@@ -179,9 +227,17 @@ async fn compose_encoded_single_range_response(
     };
 
     for enc in encds {
-        if let Some(encoded_path) = add_extension(filepath, &enc) {
-            if let Some(res) =
-                compose_single_range_response(&encoded_path, content_type, Some(enc), ranges).await
+        if let Some(encoded_path) = get_encoded_path(res_params, filepath, enc).await {
+            if let Some(res) = compose_range_response(
+                req,
+                res_params,
+                &encoded_path,
+                content_type,
+                Some(enc),
+                ranges,
+                headers,
+            )
+            .await
             {
                 return Some(res);
             }
@@ -191,78 +247,281 @@ async fn compose_encoded_single_range_response(
     None
 }
 
-async fn compose_single_range_response(
-    filepath: &PathBuf,
+async fn compose_range_response(
+    req: &Request<IncomingBody>,
+    res_params: &ResponseParams,
+    filepath: &Path,
     content_type: &str,
     content_encoding: Option<&str>,
-    ranges: &Vec<(Option<usize>, Option<usize>)>,
+    ranges: &[(Option<usize>, Option<usize>)],
+    headers: &HeaderMap,
 ) -> Option<Result<BoxedResponse, hyper::http::Error>> {
-    let mut file = match File::open(filepath).await {
+    let file = open_file(res_params, filepath).await?;
+    let metadata = match file.metadata().await {
         Ok(m) => m,
         _ => return None,
     };
 
-    let size = match file.metadata().await {
-        Ok(md) => md.len() as usize,
-        _ => return None,
-    };
+    if !metadata.is_file() {
+        return None;
+    }
 
-    let (start, end) = match get_start_and_end(ranges, size) {
-        Some(se) => se,
-        _ => {
-            return Some(last_resort_response::build_response(
-                StatusCode::BAD_REQUEST,
-                BAD_REQUEST_400,
-            ))
-        }
-    };
+    let etag = get_etag(&metadata, content_encoding);
+    let last_modified = get_last_modified(&metadata);
 
-    if let Err(_err) = file.seek(SeekFrom::Start(start.clone() as u64)).await {
+    if is_not_modified(req, &etag, &metadata) {
+        return Some(build_not_modified_response(&etag, &last_modified, headers));
+    }
+
+    // the file changed since the client's partial copy, send the whole thing
+    if !if_range_matches(req, &etag, &metadata) {
         return None;
+    }
+
+    let size = metadata.len() as usize;
+
+    let windows = match get_windows(ranges, size) {
+        Some(w) => w,
+        _ => return Some(build_range_not_satisfiable_response(size)),
     };
 
-    let mut buffer: Vec<u8> = Vec::with_capacity(end - start);
-    buffer.resize(end - start, 0);
+    if MAX_RANGES < windows.len() {
+        return None;
+    }
 
-    let content_range_header = build_content_range_header_str(&start, &end, &size);
-    let reader_stream = ReaderStream::with_capacity(file, size);
+    let mut builder = add_headers(Response::builder(), headers)
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(ETAG, etag);
+
+    if let Some(lm) = last_modified {
+        builder = builder.header(LAST_MODIFIED, lm);
+    }
+
+    if let Some(enc) = content_encoding {
+        builder = builder.header(CONTENT_ENCODING, enc);
+    }
+
+    if 1 == windows.len() {
+        return compose_single_range_response(builder, file, content_type, &windows[0], size).await;
+    }
+
+    compose_multipart_range_response(builder, file, content_type, &windows, size).await
+}
+
+async fn compose_single_range_response(
+    builder: Builder,
+    file: File,
+    content_type: &str,
+    window: &(usize, usize),
+    size: usize,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let (start, end) = window;
+
+    let reader_stream = match get_window_stream(file, *start, *end).await {
+        Ok(rs) => rs,
+        _ => return None,
+    };
+
+    let content_range_header = build_content_range_header_str(start, end, &size);
     let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
     let boxed_body = stream_body.boxed();
 
-    let mut builder = Response::builder()
-        .status(StatusCode::PARTIAL_CONTENT)
+    let builder = builder
         .header(CONTENT_TYPE, content_type)
         .header(CONTENT_RANGE, content_range_header)
-        .header(CONTENT_LENGTH, buffer.len().to_string());
+        .header(CONTENT_LENGTH, get_window_length(start, end).to_string());
 
-    if let Some(enc) = content_encoding {
-        builder = builder.header(CONTENT_ENCODING, enc);
+    Some(builder.body(boxed_body))
+}
+
+// https://www.rfc-editor.org/rfc/rfc9110#name-media-type-multipart-byteran
+async fn compose_multipart_range_response(
+    builder: Builder,
+    file: File,
+    content_type: &str,
+    windows: &[(usize, usize)],
+    size: usize,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let boundary = get_boundary();
+
+    // every part is preceded by its own headers
+    // the body closes with a final boundary
+    let mut parts: Vec<(Bytes, usize, usize)> = Vec::with_capacity(windows.len());
+    let mut content_length = 0;
+    for (index, (start, end)) in windows.iter().enumerate() {
+        let part_header = build_part_header_str(
+            &boundary,
+            content_type,
+            &build_content_range_header_str(start, end, &size),
+            0 == index,
+        );
+
+        content_length += part_header.len() + get_window_length(start, end);
+        parts.push((Bytes::from(part_header), *start, *end));
     }
 
-    return Some(builder.body(boxed_body));
+    let closing = "\r\n--".to_string() + &boundary + "--\r\n";
+    content_length += closing.len();
+
+    // parts are read one at a time, each seeks a copy of the same file
+    let file = file.into_std().await;
+    let parts_stream = stream::iter(parts)
+        .then(move |(part_header, start, end)| {
+            let part_file = file.try_clone().map(File::from_std);
+            async move {
+                let window_stream = get_window_stream(part_file?, start, end).await?;
+                let header_stream = stream::once(future::ready(Ok(part_header)));
+
+                Ok::<_, std::io::Error>(header_stream.chain(window_stream))
+            }
+        })
+        .try_flatten();
+
+    let closing_stream = stream::once(future::ready(Ok(Bytes::from(closing))));
+    let stream_body = StreamBody::new(parts_stream.chain(closing_stream).map_ok(Frame::data));
+    let boxed_body = stream_body.boxed();
+
+    let builder = builder
+        .header(
+            CONTENT_TYPE,
+            "multipart/byteranges; boundary=".to_string() + &boundary,
+        )
+        .header(CONTENT_LENGTH, content_length.to_string());
+
+    Some(builder.body(boxed_body))
+}
+
+// reads exactly the bytes of an inclusive window, chunk by chunk
+async fn get_window_stream(
+    mut file: File,
+    start: usize,
+    end: usize,
+) -> Result<ReaderStream<Take<File>>, std::io::Error> {
+    file.seek(SeekFrom::Start(start as u64)).await?;
+
+    let length = get_window_length(&start, &end);
+    let capacity = length.min(STREAM_CHUNK_SIZE);
+
+    Ok(ReaderStream::with_capacity(
+        file.take(length as u64),
+        capacity,
+    ))
+}
+
+fn get_window_length(start: &usize, end: &usize) -> usize {
+    end - start + 1
+}
+
+// unsatisfiable ranges are dropped,
+// overlapping and adjacent ranges are coalesced
+fn get_windows(
+    ranges: &[(Option<usize>, Option<usize>)],
+    size: usize,
+) -> Option<Vec<(usize, usize)>> {
+    let mut windows: Vec<(usize, usize)> = Vec::new();
+    for range in ranges {
+        if let Some(window) = get_start_and_end(range, size) {
+            windows.push(window);
+        }
+    }
+
+    windows.sort();
+
+    let mut coalesced: Vec<(usize, usize)> = Vec::with_capacity(windows.len());
+    for (start, end) in windows {
+        if let Some((_, prev_end)) = coalesced.last_mut() {
+            if start <= *prev_end + 1 {
+                *prev_end = end.max(*prev_end);
+                continue;
+            }
+        }
+
+        coalesced.push((start, end));
+    }
+
+    if !coalesced.is_empty() {
+        return Some(coalesced);
+    }
+
+    None
 }
 
 fn get_start_and_end(
-    ranges: &Vec<(Option<usize>, Option<usize>)>,
+    range: &(Option<usize>, Option<usize>),
     size: usize,
 ) -> Option<(usize, usize)> {
-    let (start, end) = match ranges.get(0) {
-        // suffix (S - N, S)
-        Some((None, Some(end))) => (size - end, size),
-        // prefix (N, S)
-        Some((Some(start), None)) => (start.clone(), size),
-        // windowed (N, M)
-        Some((Some(start), Some(end))) => (start.clone(), end.clone()),
+    // end offsets are inclusive
+    // https://www.rfc-editor.org/rfc/rfc9110#name-byte-ranges
+    let last = match size.checked_sub(1) {
+        Some(l) => l,
         _ => return None,
     };
 
-    if start <= end && end <= size {
+    let (start, end) = match range {
+        // suffix (S - N, S - 1)
+        (None, Some(0)) => return None,
+        (None, Some(length)) => (size.saturating_sub(*length), last),
+        // prefix (N, S - 1)
+        (Some(start), None) => (*start, last),
+        // windowed (N, M), M is clamped to S - 1
+        (Some(start), Some(end)) => (*start, last.min(*end)),
+        _ => return None,
+    };
+
+    if start <= end {
         return Some((start, end));
     }
 
     None
 }
 
+fn get_boundary() -> String {
+    let nanos = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_nanos(),
+        _ => 0,
+    };
+
+    let count = BOUNDARY_COUNT.fetch_add(1, Ordering::Relaxed);
+
+    format!("{:x}{:x}", nanos, count)
+}
+
+fn build_part_header_str(
+    boundary: &str,
+    content_type: &str,
+    content_range: &str,
+    is_first: bool,
+) -> String {
+    let mut part_header = match is_first {
+        true => "--".to_string(),
+        _ => "\r\n--".to_string(),
+    };
+
+    part_header += boundary;
+    part_header += "\r\nContent-Type: ";
+    part_header += content_type;
+    part_header += "\r\nContent-Range: ";
+    part_header += content_range;
+    part_header += "\r\n\r\n";
+
+    part_header
+}
+
+// https://www.rfc-editor.org/rfc/rfc9110#name-416-range-not-satisfiable
+fn build_range_not_satisfiable_response(size: usize) -> Result<BoxedResponse, hyper::http::Error> {
+    let mut res = last_resort_response::build_response(
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        RANGE_NOT_SATISFIABLE_416,
+    )?;
+
+    if let Ok(content_range) = HeaderValue::from_str(&("bytes */".to_string() + &size.to_string()))
+    {
+        res.headers_mut().insert(CONTENT_RANGE, content_range);
+    }
+
+    Ok(res)
+}
+
 fn build_content_range_header_str(start: &usize, end: &usize, size: &usize) -> String {
     "bytes ".to_string() + &start.to_string() + "-" + &end.to_string() + "/" + &size.to_string()
 }
@@ -0,0 +1,40 @@
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::header::LOCATION;
+use hyper::http::{Request, Response};
+
+use crate::response_paths::get_directory_from_url_path;
+use crate::type_flyweight::{BoxedResponse, ResponseParams};
+use crate::url_paths::encode_url_path;
+
+// "/docs" -> "/docs/" so relative links in "/docs/index.html" resolve
+pub async fn build_response(
+    req: &Request<Incoming>,
+    res_params: &ResponseParams,
+    url_path: &str,
+) -> Option<Result<BoxedResponse, hyper::http::Error>> {
+    let status_code = res_params.trailing_slash_redirect?;
+
+    if url_path.ends_with("/") {
+        return None;
+    }
+
+    get_directory_from_url_path(url_path, res_params).await?;
+
+    let mut location = encode_url_path(url_path) + "/";
+    if let Some(query) = req.uri().query() {
+        location += "?";
+        location += query;
+    }
+
+    Some(
+        Response::builder()
+            .status(status_code)
+            .header(LOCATION, location)
+            .body(
+                Full::new(bytes::Bytes::new())
+                    .map_err(|e| match e {})
+                    .boxed(),
+            ),
+    )
+}
@@ -0,0 +1,54 @@
+use hyper::header::{HeaderMap, HeaderValue, CACHE_CONTROL, VARY};
+use hyper::http::response::Builder;
+use std::path::PathBuf;
+use tokio::fs;
+
+use crate::content_type::get_content_type;
+use crate::response_paths::has_encoded_siblings;
+use crate::type_flyweight::ResponseParams;
+
+// headers shared by every representation of a served file
+pub async fn get_file_headers(res_params: &ResponseParams, filepath: &PathBuf) -> HeaderMap {
+    let mut headers = get_vary_headers(res_params, filepath).await;
+
+    let url_path = res_params.mounts.get_url_path(filepath);
+    if let Some(cache_control) = res_params.cache_control_rules.get_cache_control(&url_path) {
+        headers.insert(CACHE_CONTROL, cache_control.clone());
+    }
+
+    headers
+}
+
+// caches must not hand an encoded body to clients that can't decode it
+pub async fn get_vary_headers(res_params: &ResponseParams, filepath: &PathBuf) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    if has_encoded_siblings(res_params, filepath).await
+        || is_compressible(res_params, filepath).await
+    {
+        headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+
+    headers
+}
+
+async fn is_compressible(res_params: &ResponseParams, filepath: &PathBuf) -> bool {
+    let metadata = match fs::metadata(filepath).await {
+        Ok(m) => m,
+        _ => return false,
+    };
+
+    res_params
+        .compression
+        .is_compressible(get_content_type(filepath), metadata.len())
+}
+
+pub fn add_headers(mut builder: Builder, headers: &HeaderMap) -> Builder {
+    if let Some(builder_headers) = builder.headers_mut() {
+        for (name, value) in headers {
+            builder_headers.insert(name, value.clone());
+        }
+    }
+
+    builder
+}
@@ -3,33 +3,46 @@ use hyper::header::ACCEPT_ENCODING;
 use hyper::http::Request;
 use std::ffi::OsString;
 use std::path;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 
 use crate::available_encodings::{get_encoded_ext, AvailableEncodings};
+use crate::mounts::Mount;
+use crate::type_flyweight::ResponseParams;
 
-pub async fn get_path_from_request_url(
-    req: &Request<Incoming>,
-    directory: &PathBuf,
+// url_path must be normalized
+pub async fn get_path_from_url_path(
+    url_path: &str,
+    res_params: &ResponseParams,
 ) -> Option<PathBuf> {
-    let uri_path = req.uri().path();
+    let (mount, mounted_path) = get_mounted_path(url_path, res_params)?;
 
-    let stripped = match uri_path.strip_prefix("/") {
-        Some(p) => p,
-        _ => uri_path,
-    };
+    let filepath = get_path(mount, &mounted_path, res_params).await?;
+
+    // deny patterns also match index files served for a directory
+    if res_params
+        .path_policy
+        .is_denied(&res_params.mounts.get_url_path(&filepath))
+    {
+        return None;
+    }
 
-    get_path(directory, &PathBuf::from(stripped)).await
+    Some(filepath)
 }
 
-pub async fn get_path(directory: &PathBuf, filepath: &PathBuf) -> Option<PathBuf> {
-    let mut target_path = match path::absolute(directory.join(&filepath)) {
+// url_path must be normalized
+pub async fn get_directory_from_url_path(
+    url_path: &str,
+    res_params: &ResponseParams,
+) -> Option<PathBuf> {
+    let (mount, mounted_path) = get_mounted_path(url_path, res_params)?;
+
+    let target_path = match path::absolute(mount.directory.join(mounted_path)) {
         Ok(pb) => pb,
         _ => return None,
     };
 
-    // confirm path resides in directory
-    if !target_path.starts_with(directory) {
+    if !target_path.starts_with(&mount.directory) {
         return None;
     }
 
@@ -38,62 +51,295 @@ pub async fn get_path(directory: &PathBuf, filepath: &PathBuf) -> Option<PathBuf
         _ => return None,
     };
 
-    // if file bail early
-    if metadata.is_file() {
-        return Some(target_path);
+    if !metadata.is_dir() || !res_params.path_policy.allows(mount, &target_path).await {
+        return None;
     }
 
-    // if directory try an index.html file
-    if metadata.is_dir() {
-        target_path.push("index.html");
+    Some(target_path)
+}
 
-        let updated_metadata = match fs::metadata(&target_path).await {
-            Ok(md) => md,
-            _ => return None,
-        };
+// url_path must be normalized
+pub async fn get_spa_path(url_path: &str, res_params: &ResponseParams) -> Option<PathBuf> {
+    let spa_fallback = res_params.spa_fallback.as_ref()?;
+    if spa_fallback.excludes(url_path) {
+        return None;
+    }
+
+    // file starts with a mounted directory
+    let mount = res_params.mounts.get_file_mount(&spa_fallback.filepath)?;
+    get_path(mount, &spa_fallback.filepath, res_params).await
+}
+
+// files are only opened through the path policy
+pub async fn open_file(res_params: &ResponseParams, filepath: &Path) -> Option<fs::File> {
+    let mount = res_params.mounts.get_file_mount(filepath)?;
+    res_params.path_policy.open(mount, filepath).await
+}
+
+fn get_mounted_path<'a>(
+    url_path: &str,
+    res_params: &'a ResponseParams,
+) -> Option<(&'a Mount, PathBuf)> {
+    let (mount, mounted_path) = res_params.mounts.get_mount(url_path)?;
+
+    let stripped = match mounted_path.strip_prefix("/") {
+        Some(p) => p,
+        _ => mounted_path,
+    };
+
+    Some((mount, PathBuf::from(stripped)))
+}
+
+pub async fn get_path(
+    mount: &Mount,
+    filepath: &PathBuf,
+    res_params: &ResponseParams,
+) -> Option<PathBuf> {
+    let target_path = match path::absolute(mount.directory.join(filepath)) {
+        Ok(pb) => pb,
+        _ => return None,
+    };
 
-        if updated_metadata.is_file() {
+    // confirm path resides in directory
+    if !target_path.starts_with(&mount.directory) {
+        return None;
+    }
+
+    // "/about/" is a directory, never "about/.html"
+    let try_extensions = !filepath.to_string_lossy().ends_with("/");
+    let target_path = get_file(res_params, target_path, try_extensions).await?;
+
+    if !res_params.path_policy.allows(mount, &target_path).await {
+        return None;
+    }
+
+    Some(target_path)
+}
+
+// like nginx try_files: the file, then index files, then extensions
+async fn get_file(
+    res_params: &ResponseParams,
+    target_path: PathBuf,
+    try_extensions: bool,
+) -> Option<PathBuf> {
+    if let Ok(metadata) = fs::metadata(&target_path).await {
+        if metadata.is_file() {
             return Some(target_path);
         }
+
+        // if directory try index files
+        if metadata.is_dir() {
+            for index_file in &res_params.index_files {
+                let index_path = target_path.join(index_file);
+                if is_file(&index_path).await {
+                    return Some(index_path);
+                }
+            }
+        }
+    }
+
+    if !try_extensions {
+        return None;
+    }
+
+    // "/about" -> "about.html"
+    for extension in &res_params.try_extensions {
+        let mut fp_with_ext = OsString::from(&target_path);
+        fp_with_ext.push(".");
+        fp_with_ext.push(extension);
+
+        let extension_path = PathBuf::from(fp_with_ext);
+        if is_file(&extension_path).await {
+            return Some(extension_path);
+        }
     }
 
     None
 }
 
+async fn is_file(filepath: &Path) -> bool {
+    match fs::metadata(filepath).await {
+        Ok(md) => md.is_file(),
+        _ => false,
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc9110#name-accept-encoding
+// encodings are sorted by quality, ties go to the configured order
 pub fn get_encodings(
     req: &Request<Incoming>,
     available_encodings: &AvailableEncodings,
 ) -> Option<Vec<String>> {
-    let accept_encoding_header = match req.headers().get(ACCEPT_ENCODING) {
-        Some(enc) => enc,
-        _ => return None,
+    let qualities = get_encoding_qualities(req)?;
+
+    let mut encodings: Vec<(u16, String)> = Vec::new();
+    for encoding in available_encodings.get_encodings() {
+        let quality = match get_quality(&qualities, encoding) {
+            Some(q) => q,
+            _ => continue,
+        };
+
+        if 0 < quality {
+            encodings.push((quality, encoding.to_string()));
+        }
+    }
+
+    // stable sort keeps configured order for equal qualities
+    encodings.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    if !encodings.is_empty() {
+        return Some(encodings.into_iter().map(|(_, enc)| enc).collect());
+    }
+
+    None
+}
+
+// identity is acceptable unless excluded by "identity;q=0" or "*;q=0"
+pub fn identity_is_acceptable(req: &Request<Incoming>) -> bool {
+    let qualities = match get_encoding_qualities(req) {
+        Some(q) => q,
+        _ => return true,
     };
 
+    match get_quality(&qualities, "identity") {
+        Some(q) => 0 < q,
+        _ => true,
+    }
+}
+
+// explicit codings take precedence over "*"
+fn get_quality(qualities: &[(String, u16)], encoding: &str) -> Option<u16> {
+    let mut wildcard = None;
+    for (name, quality) in qualities {
+        if name == encoding {
+            return Some(*quality);
+        }
+
+        if "*" == name {
+            wildcard = Some(*quality);
+        }
+    }
+
+    wildcard
+}
+
+// qualities are stored in thousandths: "q=0.5" -> 500
+fn get_encoding_qualities(req: &Request<Incoming>) -> Option<Vec<(String, u16)>> {
+    let accept_encoding_header = req.headers().get(ACCEPT_ENCODING)?;
+
     let encoding_str = match accept_encoding_header.to_str() {
         Ok(s) => s,
         _ => return None,
     };
 
-    let mut encodings = Vec::new();
+    let mut qualities = Vec::new();
     for encoding in encoding_str.split(",") {
-        let trimmed = encoding.trim();
-        if available_encodings.encoding_is_available(trimmed) {
-            encodings.push(trimmed.to_string());
+        let mut params = encoding.split(";");
+
+        let name = match params.next() {
+            Some(n) => n.trim().to_ascii_lowercase(),
+            _ => continue,
+        };
+
+        if name.is_empty() {
+            continue;
+        }
+
+        let mut quality = Some(1000);
+        for param in params {
+            if let Some((key, value)) = param.split_once("=") {
+                if key.trim().eq_ignore_ascii_case("q") {
+                    quality = get_quality_value(value.trim());
+                }
+            }
+        }
+
+        if let Some(q) = quality {
+            qualities.push((name, q));
         }
     }
 
-    if 0 < encodings.len() {
-        return Some(encodings);
+    Some(qualities)
+}
+
+fn get_quality_value(value: &str) -> Option<u16> {
+    let quality: f32 = match value.parse() {
+        Ok(q) => q,
+        _ => return None,
+    };
+
+    if (0.0..=1.0).contains(&quality) {
+        return Some((quality * 1000.0).round() as u16);
     }
 
     None
 }
 
+// filepath relative to directory as a url path: "/static/app.js"
+pub fn get_url_path(directory: &Path, filepath: &Path) -> String {
+    let relative = match filepath.strip_prefix(directory) {
+        Ok(r) => r,
+        _ => filepath,
+    };
+
+    let mut url_path = String::new();
+    for component in relative.components() {
+        url_path.push('/');
+        url_path += &component.as_os_str().to_string_lossy();
+    }
+
+    if url_path.is_empty() {
+        url_path.push('/');
+    }
+
+    url_path
+}
+
+pub async fn has_encoded_siblings(res_params: &ResponseParams, filepath: &PathBuf) -> bool {
+    for encoding in res_params.available_encodings.get_encodings() {
+        let encoded_path = match get_encoded_path(res_params, filepath, encoding).await {
+            Some(ep) => ep,
+            _ => continue,
+        };
+
+        if let Ok(metadata) = fs::metadata(encoded_path).await {
+            if metadata.is_file() {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+// encoded siblings follow the same path policy as the file
+pub async fn get_encoded_path(
+    res_params: &ResponseParams,
+    filepath: &PathBuf,
+    encoding: &str,
+) -> Option<PathBuf> {
+    let encoded_path = add_extension(filepath, encoding)?;
+    let mount = res_params.mounts.get_file_mount(&encoded_path)?;
+
+    if res_params
+        .path_policy
+        .is_denied(&res_params.mounts.get_url_path(&encoded_path))
+    {
+        return None;
+    }
+
+    if !res_params.path_policy.allows(mount, &encoded_path).await {
+        return None;
+    }
+
+    Some(encoded_path)
+}
+
 // nightly API replacement
 // https://doc.rust-lang.org/std/path/struct.Path.html#method.with_added_extension
 
 // Filepath must be a file, not a directory for this to work.
-pub fn add_extension(filepath: &PathBuf, encoding: &str) -> Option<PathBuf> {
+fn add_extension(filepath: &PathBuf, encoding: &str) -> Option<PathBuf> {
     let enc_ext = match get_encoded_ext(encoding) {
         Some(enc) => enc,
         _ => return None,
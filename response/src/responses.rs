@@ -1,4 +1,5 @@
 use hyper::body::Incoming;
+use hyper::header::VARY;
 use hyper::http::Request;
 use hyper::Method;
 use hyper::StatusCode;
@@ -6,18 +7,48 @@ use hyper::StatusCode;
 use crate::get_response;
 use crate::head_response;
 use crate::last_resort_response;
-use crate::type_flyweight::{BoxedResponse, ResponseParams, METHOD_NOT_ALLOWED_405};
+use crate::type_flyweight::{
+    BoxedResponse, ResponseParams, BAD_REQUEST_400, FORBIDDEN_403, METHOD_NOT_ALLOWED_405,
+};
+use crate::url_paths::normalize_url_path;
 
 pub async fn build_response(
     req: Request<Incoming>,
     res_params: ResponseParams,
 ) -> Result<BoxedResponse, hyper::http::Error> {
-    match req.method() {
-        &Method::GET => get_response::build_response(req, res_params).await,
-        &Method::HEAD => head_response::build_response(req, res_params).await,
+    let url_path = match normalize_url_path(req.uri().path()) {
+        Some(up) => up,
+        _ => return last_resort_response::build_response(StatusCode::BAD_REQUEST, BAD_REQUEST_400),
+    };
+
+    // custom headers apply to every response for a path
+    let headers = res_params.header_rules.get_headers(&url_path);
+
+    let res = match *req.method() {
+        _ if res_params.path_policy.is_forbidden(&url_path) => {
+            last_resort_response::build_response(StatusCode::FORBIDDEN, FORBIDDEN_403)
+        }
+        Method::GET => get_response::build_response(req, res_params, &url_path).await,
+        Method::HEAD => head_response::build_response(req, res_params, &url_path).await,
         _ => last_resort_response::build_response(
             StatusCode::METHOD_NOT_ALLOWED,
             METHOD_NOT_ALLOWED_405,
         ),
-    }
+    };
+
+    // rule headers replace the response's own except vary, which is appended
+    // so content negotiation is never hidden from caches
+    res.map(|mut response| {
+        for name in headers.keys() {
+            if VARY != name {
+                response.headers_mut().remove(name);
+            }
+        }
+
+        for (name, value) in headers.iter() {
+            response.headers_mut().append(name, value.clone());
+        }
+
+        response
+    })
 }
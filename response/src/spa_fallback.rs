@@ -0,0 +1,39 @@
+use config::SpaFallbackConfig;
+use std::path::PathBuf;
+
+use crate::path_patterns::pattern_matches;
+
+// paths that look like files still 404
+const DEFAULT_EXCLUDE_PATTERNS: [&str; 1] = ["*.*"];
+
+// unknown paths are served an app's entry file
+#[derive(Clone, Debug)]
+pub struct SpaFallback {
+    pub filepath: PathBuf,
+    exclude_patterns: Vec<String>,
+}
+
+impl SpaFallback {
+    pub fn from(potential_spa_fallback: Option<SpaFallbackConfig>) -> Option<SpaFallback> {
+        let spa_fallback = potential_spa_fallback?;
+
+        let exclude_patterns = match spa_fallback.exclude_patterns {
+            Some(patterns) => patterns,
+            _ => DEFAULT_EXCLUDE_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+        };
+
+        Some(SpaFallback {
+            filepath: spa_fallback.filepath,
+            exclude_patterns,
+        })
+    }
+
+    pub fn excludes(&self, url_path: &str) -> bool {
+        self.exclude_patterns
+            .iter()
+            .any(|pattern| pattern_matches(pattern, url_path))
+    }
+}
@@ -1,37 +1,87 @@
 use bytes::Bytes;
+use config::Config;
 use http_body_util::combinators::BoxBody;
-use hyper::Response;
+use hyper::{Response, StatusCode};
 use std::path::PathBuf;
 use tokio::io;
 
 use crate::available_encodings::AvailableEncodings;
+use crate::cache_control::CacheControlRules;
+use crate::compression::Compression;
+use crate::custom_headers::HeaderRules;
+use crate::mounts::Mounts;
+use crate::path_policy::PathPolicy;
+use crate::spa_fallback::SpaFallback;
 
 pub type BoxedResponse = Response<BoxBody<Bytes, io::Error>>;
 
 pub const BAD_REQUEST_400: &str = "400 bad request";
+pub const FORBIDDEN_403: &str = "403 forbidden";
 pub const NOT_FOUND_404: &str = "404 not found";
 pub const METHOD_NOT_ALLOWED_405: &str = "405 method not allowed";
+pub const NOT_ACCEPTABLE_406: &str = "406 not acceptable";
 pub const RANGE_NOT_SATISFIABLE_416: &str = "416 range not satisfiable";
 
 #[derive(Clone, Debug)]
 pub struct ResponseParams {
-    pub directory: PathBuf,
+    pub mounts: Mounts,
+    pub path_policy: PathPolicy,
     pub available_encodings: AvailableEncodings,
     pub filepath_404: Option<PathBuf>,
+    pub cache_control_rules: CacheControlRules,
+    pub header_rules: HeaderRules,
+    pub compression: Compression,
+    pub autoindex: bool,
+    pub trailing_slash_redirect: Option<StatusCode>,
+    pub index_files: Vec<String>,
+    pub try_extensions: Vec<String>,
+    pub spa_fallback: Option<SpaFallback>,
 }
 
 impl ResponseParams {
-    pub fn from(
-        directory: PathBuf,
-        filepath_404: Option<PathBuf>,
-        content_encodings: Option<Vec<String>>,
-    ) -> ResponseParams {
-        let available_encodings = AvailableEncodings::from(content_encodings);
+    pub fn from(config: Config) -> ResponseParams {
+        let compression = Compression::from(config.compression.clone());
+        ResponseParams::from_compression(config, compression)
+    }
+
+    // sites share the top level compression and its cache directory
+    pub fn from_site(config: Config, response_params: &ResponseParams) -> ResponseParams {
+        ResponseParams::from_compression(config, response_params.compression.clone())
+    }
+
+    fn from_compression(config: Config, compression: Compression) -> ResponseParams {
+        let path_policy = PathPolicy::from(&config);
+        let available_encodings = AvailableEncodings::from(config.content_encodings);
+        let cache_control_rules = CacheControlRules::from(config.cache_control);
+        let header_rules = HeaderRules::from(config.headers);
+        let mounts = Mounts::from(config.directory, config.mounts);
 
         ResponseParams {
-            directory: directory,
-            available_encodings: available_encodings,
-            filepath_404: filepath_404,
+            mounts,
+            path_policy,
+            available_encodings,
+            filepath_404: config.filepath_404,
+            cache_control_rules,
+            header_rules,
+            compression,
+            autoindex: config.autoindex.unwrap_or(false),
+            trailing_slash_redirect: match config.trailing_slash_redirect {
+                Some(status) => StatusCode::from_u16(status).ok(),
+                _ => None,
+            },
+            index_files: match config.index_files {
+                Some(index_files) => index_files,
+                _ => vec!["index.html".to_string()],
+            },
+            // "html" and ".html" are the same extension
+            try_extensions: match config.try_extensions {
+                Some(extensions) => extensions
+                    .iter()
+                    .map(|ext| ext.trim_start_matches(".").to_string())
+                    .collect(),
+                _ => Vec::new(),
+            },
+            spa_fallback: SpaFallback::from(config.spa_fallback),
         }
     }
 }
@@ -0,0 +1,172 @@
+// https://www.rfc-editor.org/rfc/rfc3986#section-2.1
+// https://www.rfc-editor.org/rfc/rfc3986#section-5.2.4
+
+// percent-decoded url path without empty, "." or ".." segments
+// "/a//b/./c/../d%20e" -> "/a/b/d e"
+//
+// returns None for malformed encodings, invalid utf-8,
+// and encoded "/" or NUL bytes
+pub fn normalize_url_path(uri_path: &str) -> Option<String> {
+    let mut segments: Vec<String> = Vec::new();
+    let mut trailing_slash = false;
+
+    for segment in uri_path.split("/") {
+        let decoded = percent_decode(segment)?;
+
+        // ".." never climbs above the root
+        trailing_slash = match decoded.as_str() {
+            "" => true,
+            "." => true,
+            ".." => {
+                segments.pop();
+                true
+            }
+            _ => {
+                segments.push(decoded);
+                false
+            }
+        };
+    }
+
+    let mut url_path = "/".to_string() + &segments.join("/");
+    if trailing_slash && !segments.is_empty() {
+        url_path.push('/');
+    }
+
+    Some(url_path)
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut index = 0;
+    while index < bytes.len() {
+        let byte = bytes[index];
+        if b'%' != byte {
+            decoded.push(byte);
+            index += 1;
+            continue;
+        }
+
+        let high = get_hex_value(*bytes.get(index + 1)?)?;
+        let low = get_hex_value(*bytes.get(index + 2)?)?;
+        let decoded_byte = high * 16 + low;
+        if b'/' == decoded_byte || 0 == decoded_byte {
+            return None;
+        }
+
+        decoded.push(decoded_byte);
+        index += 3;
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn get_hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+// every byte except unreserved characters is encoded
+pub fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded += &format!("%{:02X}", byte),
+        }
+    }
+
+    encoded
+}
+
+pub fn encode_url_path(url_path: &str) -> String {
+    let segments: Vec<String> = url_path.split("/").map(percent_encode).collect();
+    segments.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_percent_encodings() {
+        assert_eq!(
+            normalize_url_path("/my%20file.txt"),
+            Some("/my file.txt".to_string())
+        );
+        assert_eq!(
+            normalize_url_path("/h%C3%A9llo.txt"),
+            Some("/héllo.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn encoded_dot_segments_are_removed() {
+        assert_eq!(normalize_url_path("/a/%2e%2e/b"), Some("/b".to_string()));
+        assert_eq!(
+            normalize_url_path("/%2E%2E/%2e%2e/etc/passwd"),
+            Some("/etc/passwd".to_string())
+        );
+        assert_eq!(normalize_url_path("/a/%2e/b"), Some("/a/b".to_string()));
+    }
+
+    #[test]
+    fn dot_dot_never_climbs_above_the_root() {
+        assert_eq!(
+            normalize_url_path("/../../etc/passwd"),
+            Some("/etc/passwd".to_string())
+        );
+        assert_eq!(normalize_url_path("/a/../../b"), Some("/b".to_string()));
+        assert_eq!(normalize_url_path("/.."), Some("/".to_string()));
+    }
+
+    #[test]
+    fn encoded_slashes_are_rejected() {
+        assert_eq!(normalize_url_path("/a%2fb"), None);
+        assert_eq!(normalize_url_path("/..%2F..%2Fetc/passwd"), None);
+    }
+
+    #[test]
+    fn encoded_nul_bytes_are_rejected() {
+        assert_eq!(normalize_url_path("/index.html%00.txt"), None);
+    }
+
+    #[test]
+    fn truncated_encodings_are_rejected() {
+        assert_eq!(normalize_url_path("/a%4"), None);
+        assert_eq!(normalize_url_path("/a%"), None);
+        assert_eq!(normalize_url_path("/a%zz"), None);
+    }
+
+    #[test]
+    fn invalid_utf8_is_rejected() {
+        assert_eq!(normalize_url_path("/%ff"), None);
+        assert_eq!(normalize_url_path("/%c3"), None);
+    }
+
+    #[test]
+    fn duplicate_slashes_are_removed() {
+        assert_eq!(
+            normalize_url_path("//a///b//c.txt"),
+            Some("/a/b/c.txt".to_string())
+        );
+        assert_eq!(normalize_url_path("//"), Some("/".to_string()));
+    }
+
+    #[test]
+    fn trailing_slashes_are_preserved() {
+        assert_eq!(normalize_url_path("/docs/"), Some("/docs/".to_string()));
+        assert_eq!(normalize_url_path("/docs//"), Some("/docs/".to_string()));
+        assert_eq!(normalize_url_path("/docs/a/.."), Some("/docs/".to_string()));
+        assert_eq!(normalize_url_path("/docs"), Some("/docs".to_string()));
+        assert_eq!(normalize_url_path("/"), Some("/".to_string()));
+    }
+}
@@ -0,0 +1,156 @@
+use http_body_util::{BodyExt, Full};
+use httpdate::HttpDate;
+use hyper::body::Incoming;
+use hyper::header::{
+    HeaderMap, HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED,
+};
+use hyper::http::{Request, Response};
+use hyper::StatusCode;
+use std::fs::Metadata;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::response_headers::add_headers;
+use crate::type_flyweight::BoxedResponse;
+
+// https://www.rfc-editor.org/rfc/rfc9110#name-validator-fields
+
+// strong etags are derived from metadata: inode, size, and mtime
+// encoded variants carry their encoding so each representation is distinct
+pub fn get_etag(metadata: &Metadata, content_encoding: Option<&str>) -> String {
+    let mtime = match get_modified(metadata) {
+        Some(m) => match m.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_nanos(),
+            _ => 0,
+        },
+        _ => 0,
+    };
+
+    let mut etag = format!(
+        "\"{:x}-{:x}-{:x}",
+        get_inode(metadata),
+        metadata.len(),
+        mtime
+    );
+    if let Some(enc) = content_encoding {
+        etag += "-";
+        etag += enc;
+    }
+    etag += "\"";
+
+    etag
+}
+
+pub fn get_last_modified(metadata: &Metadata) -> Option<String> {
+    get_modified(metadata).map(httpdate::fmt_http_date)
+}
+
+// If-None-Match takes precedence over If-Modified-Since
+pub fn is_not_modified(req: &Request<Incoming>, etag: &str, metadata: &Metadata) -> bool {
+    if let Some(if_none_match) = get_header_str(req, IF_NONE_MATCH) {
+        return etag_list_matches(if_none_match, etag);
+    }
+
+    let if_modified_since = match get_header_str(req, IF_MODIFIED_SINCE) {
+        Some(ims) => ims,
+        _ => return false,
+    };
+
+    let since: HttpDate = match if_modified_since.trim().parse() {
+        Ok(d) => d,
+        _ => return false,
+    };
+
+    match get_modified(metadata) {
+        Some(modified) => HttpDate::from(modified) <= since,
+        _ => false,
+    }
+}
+
+// a missing If-Range always matches
+// etags use strong comparison, dates must be an exact match
+pub fn if_range_matches(req: &Request<Incoming>, etag: &str, metadata: &Metadata) -> bool {
+    let if_range = match get_header_str(req, IF_RANGE) {
+        Some(ir) => ir.trim(),
+        _ => return true,
+    };
+
+    if if_range.starts_with("\"") || if_range.starts_with("W/") {
+        return if_range == etag;
+    }
+
+    let date: HttpDate = match if_range.parse() {
+        Ok(d) => d,
+        _ => return false,
+    };
+
+    match get_modified(metadata) {
+        Some(modified) => HttpDate::from(modified) == date,
+        _ => false,
+    }
+}
+
+pub fn build_not_modified_response(
+    etag: &str,
+    last_modified: &Option<String>,
+    headers: &HeaderMap,
+) -> Result<BoxedResponse, hyper::http::Error> {
+    let mut builder = add_headers(Response::builder(), headers)
+        .status(StatusCode::NOT_MODIFIED)
+        .header(ETAG, etag);
+
+    if let Some(lm) = last_modified {
+        builder = builder.header(LAST_MODIFIED, lm);
+    }
+
+    builder.body(
+        Full::new(bytes::Bytes::new())
+            .map_err(|e| match e {})
+            .boxed(),
+    )
+}
+
+// weak comparison
+fn etag_list_matches(etag_list: &str, etag: &str) -> bool {
+    let etag_opaque = get_opaque_tag(etag);
+
+    for candidate in etag_list.split(",") {
+        let trimmed = candidate.trim();
+        if "*" == trimmed {
+            return true;
+        }
+
+        if get_opaque_tag(trimmed) == etag_opaque {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn get_opaque_tag(etag: &str) -> &str {
+    match etag.strip_prefix("W/") {
+        Some(o) => o,
+        _ => etag,
+    }
+}
+
+fn get_header_str(req: &Request<Incoming>, name: HeaderName) -> Option<&str> {
+    match req.headers().get(name) {
+        Some(value) => value.to_str().ok(),
+        _ => None,
+    }
+}
+
+fn get_modified(metadata: &Metadata) -> Option<SystemTime> {
+    metadata.modified().ok()
+}
+
+#[cfg(unix)]
+fn get_inode(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+#[cfg(not(unix))]
+fn get_inode(_metadata: &Metadata) -> u64 {
+    0
+}